@@ -0,0 +1,64 @@
+// Credentials for registries, which endpoints that talk to a registry on
+// the client's behalf, such as pushing an image, take in the
+// `X-Registry-Auth` header
+
+use base64;
+use serde_json;
+
+use utils::http::Request;
+
+pub const REGISTRY_AUTH_HEADER: &str = "X-Registry-Auth";
+
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct RegistryAuth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // The registry the credentials are for, such as `registry.example.com:5000`
+    #[serde(rename = "serveraddress", skip_serializing_if = "Option::is_none")]
+    pub server_address: Option<String>,
+    // A token from a previous login, used instead of the username and
+    // password
+    #[serde(rename = "identitytoken", skip_serializing_if = "Option::is_none")]
+    pub identity_token: Option<String>,
+}
+
+impl RegistryAuth {
+    pub fn password(username: &str, password: &str, server_address: &str) -> RegistryAuth {
+        RegistryAuth {
+            username: Some(String::from(username)),
+            password: Some(String::from(password)),
+            server_address: Some(String::from(server_address)),
+            identity_token: None,
+        }
+    }
+
+    pub fn identity_token(token: &str, server_address: &str) -> RegistryAuth {
+        RegistryAuth {
+            server_address: Some(String::from(server_address)),
+            identity_token: Some(String::from(token)),
+            ..RegistryAuth::default()
+        }
+    }
+
+    // The value of the `X-Registry-Auth` header, which is the credentials as
+    // JSON in URL safe base64
+    pub fn to_header(&self) -> String {
+        // Only strings are serialized, which cannot fail
+        let json = serde_json::to_string(self).unwrap();
+        base64::encode_config(json.as_bytes(), base64::URL_SAFE)
+    }
+}
+
+// Add the `X-Registry-Auth` header to a request. The engine requires it
+// even for registries which need no credentials, so without any an empty
+// set is sent.
+pub fn set_header(req: &mut Request, auth: Option<&RegistryAuth>) {
+    let header = match auth {
+        Some(auth) => auth.to_header(),
+        None => RegistryAuth::default().to_header(),
+    };
+    req.headers
+        .insert(String::from(REGISTRY_AUTH_HEADER), header);
+}
@@ -0,0 +1,273 @@
+// Building images from a Dockerfile, with the build context sent to the
+// engine as a tar archive
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use serde_json;
+
+use errors::*;
+use filters::BuildCachePruneFilters;
+use network::{expect_status, gen_request, get, post, stream_request};
+use platform::Platform;
+use progress::{ProgressMessage, ProgressSink};
+use queryparameters::{generate_path, QueryParameters};
+use reference::Reference;
+use types::Client;
+
+// Typed options for building an image with `image`
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    tags: Vec<Reference>,
+    dockerfile: Option<String>,
+    build_args: BTreeMap<String, String>,
+    labels: BTreeMap<String, String>,
+    target: Option<String>,
+    no_cache: bool,
+    pull: bool,
+    platform: Option<Platform>,
+}
+
+impl BuildOptions {
+    pub fn new() -> BuildOptions {
+        BuildOptions::default()
+    }
+
+    // Name the built image, which can be given more than once
+    pub fn tag(mut self, image: Reference) -> BuildOptions {
+        self.tags.push(image);
+        self
+    }
+
+    // The path of the Dockerfile within the context, when it is not
+    // `Dockerfile`
+    pub fn dockerfile(mut self, dockerfile: &str) -> BuildOptions {
+        self.dockerfile = Some(String::from(dockerfile));
+        self
+    }
+
+    pub fn build_arg(mut self, name: &str, value: &str) -> BuildOptions {
+        self.build_args
+            .insert(String::from(name), String::from(value));
+        self
+    }
+
+    pub fn label(mut self, name: &str, value: &str) -> BuildOptions {
+        self.labels.insert(String::from(name), String::from(value));
+        self
+    }
+
+    // The stage of a multi-stage Dockerfile to build
+    pub fn target(mut self, target: &str) -> BuildOptions {
+        self.target = Some(String::from(target));
+        self
+    }
+
+    pub fn no_cache(mut self, no_cache: bool) -> BuildOptions {
+        self.no_cache = no_cache;
+        self
+    }
+
+    // Pull newer versions of base images, even when they are present
+    pub fn pull(mut self, pull: bool) -> BuildOptions {
+        self.pull = pull;
+        self
+    }
+
+    pub fn platform(mut self, platform: Platform) -> BuildOptions {
+        self.platform = Some(platform);
+        self
+    }
+
+    pub fn to_query_parameters(&self) -> QueryParameters {
+        let mut params = QueryParameters::new();
+        for tag in &self.tags {
+            params.add("t", tag.familiar());
+        }
+        if let Some(ref dockerfile) = self.dockerfile {
+            params.add("dockerfile", dockerfile);
+        }
+        // Maps of strings can always be serialized
+        if !self.build_args.is_empty() {
+            params.add(
+                "buildargs",
+                serde_json::to_string(&self.build_args).unwrap(),
+            );
+        }
+        if !self.labels.is_empty() {
+            params.add("labels", serde_json::to_string(&self.labels).unwrap());
+        }
+        if let Some(ref target) = self.target {
+            params.add("target", target);
+        }
+        if self.no_cache {
+            params.add("nocache", true);
+        }
+        if self.pull {
+            params.add("pull", true);
+        }
+        if let Some(ref platform) = self.platform {
+            params.add("platform", platform);
+        }
+        params
+    }
+}
+
+// The aux message which reports the built image
+#[derive(Deserialize)]
+struct BuildAux {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+// The ID of the image a build produced, from the messages it returned
+pub fn built_id(messages: &[ProgressMessage]) -> Option<String> {
+    messages
+        .iter()
+        .rev()
+        .filter_map(|m| m.aux.clone())
+        .filter_map(|aux| serde_json::from_value::<BuildAux>(aux).ok())
+        .map(|aux| aux.id)
+        .next()
+}
+
+// An entry in the build cache, as returned by `cache`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct BuildCache {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: String,
+    #[serde(default)]
+    pub parents: Option<Vec<String>>,
+    // Such as `regular`, `source.local` or `exec.cachemount`
+    #[serde(rename(deserialize = "Type"))]
+    pub cache_type: String,
+    pub description: String,
+    pub in_use: bool,
+    pub shared: bool,
+    pub size: i64,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub usage_count: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct DiskUsage {
+    build_cache: Option<Vec<BuildCache>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PruneResponse {
+    pub caches_deleted: Option<Vec<String>>,
+    pub space_reclaimed: u64,
+}
+
+// Build endpoints, as returned by `Client::build`
+pub struct Builder<'a> {
+    client: &'a Client,
+}
+
+impl<'a> Builder<'a> {
+    pub fn new(client: &'a Client) -> Builder<'a> {
+        Builder { client }
+    }
+
+    // Build an image from a tar archive of the build context, such as a
+    // `context::BuildContext`, returning the engine's output. A failing
+    // build step returns a StreamError.
+    pub fn image<R: Read>(
+        &self,
+        context: R,
+        options: &BuildOptions,
+    ) -> Result<Vec<ProgressMessage>> {
+        let mut messages = Vec::new();
+        self.image_with_progress(context, options, |m| messages.push(m))?;
+        Ok(messages)
+    }
+
+    // As `image`, but passing each message to `on_progress` as it arrives
+    pub fn image_with_progress<R, F>(
+        &self,
+        mut context: R,
+        options: &BuildOptions,
+        on_progress: F,
+    ) -> Result<()>
+    where
+        R: Read,
+        F: FnMut(ProgressMessage),
+    {
+        let path = generate_path("/build", Some(&mut options.to_query_parameters()));
+
+        let mut req = gen_request("POST", &path, None);
+        req.headers.insert(
+            String::from("Content-Type"),
+            String::from("application/x-tar"),
+        );
+
+        let mut progress = ProgressSink::new(&path, on_progress);
+        let response = stream_request(self.client, req, Some(&mut context), &mut progress)?;
+        expect_status(&response, &path, 200)?;
+        progress.finish()
+    }
+
+    // The entries in the engine's build cache, from its disk usage report
+    pub fn cache(&self) -> Result<Vec<BuildCache>> {
+        // Engines which do not support `type` report everything
+        let path = "/system/df?type=build-cache";
+
+        let response = get(self.client, path)?;
+        expect_status(&response, path, 200)?;
+
+        let usage: DiskUsage = serde_json::from_str(&response.body)
+            .chain_err(|| "Failed to deserialize disk usage response")?;
+        Ok(usage.build_cache.unwrap_or_default())
+    }
+
+    // Remove unused build cache. Without `all`, only dangling cache which
+    // no image refers to is removed, and with `keep_storage` pruning stops
+    // once the cache fits in that many bytes.
+    pub fn prune(
+        &self,
+        all: bool,
+        keep_storage: Option<u64>,
+        filters: Option<&BuildCachePruneFilters>,
+    ) -> Result<PruneResponse> {
+        let mut args = QueryParameters::new();
+        args.add("all", all);
+        if let Some(keep_storage) = keep_storage {
+            args.add("keep-storage", keep_storage);
+        }
+        if let Some(filters) = filters {
+            args.add_filters(filters);
+        }
+        let path = generate_path("/build/prune", Some(&mut args));
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 200)?;
+
+        serde_json::from_str(&response.body).chain_err(|| "Failed to deserialize prune response")
+    }
+}
+
+pub fn cache(client: &Client) -> Result<Vec<BuildCache>> {
+    client.build().cache()
+}
+
+pub fn prune(
+    client: &Client,
+    all: bool,
+    keep_storage: Option<u64>,
+    filters: Option<&BuildCachePruneFilters>,
+) -> Result<PruneResponse> {
+    client.build().prune(all, keep_storage, filters)
+}
+
+pub fn image<R: Read>(
+    client: &Client,
+    context: R,
+    options: &BuildOptions,
+) -> Result<Vec<ProgressMessage>> {
+    client.build().image(context, options)
+}
@@ -0,0 +1,288 @@
+// Record and replay of engine traffic, so that code driven by narwhal can
+// be tested without a docker daemon. A client with a cassette attached via
+// `Client::record_to` saves every request and response it exchanges with
+// the engine, and a client created with `Client::new_replay` answers
+// requests from that cassette instead of connecting to anything.
+//
+// Streamed responses, such as image exports, are recorded and replayed too,
+// but bodies streamed to the engine are not recorded, so those requests are
+// matched without them.
+
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64;
+use serde_json;
+
+use errors::*;
+use httpstream::{Connector, HttpStream};
+use types::Client;
+use utils::http::{Request, Response};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Interaction {
+    pub request: Request,
+    pub response: Response,
+    // The base64 encoded body passed to the sink of a streamed request, which
+    // is left out of `response`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streamed: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Default)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    // Which interactions have been served during replay
+    played: Vec<bool>,
+}
+
+// A set of recorded interactions backed by a JSON file. Clones share the
+// same interactions.
+#[derive(Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl Cassette {
+    // An empty cassette, which will overwrite the file at `path` once
+    // something is recorded
+    pub fn new<P: AsRef<Path>>(path: P) -> Cassette {
+        Cassette {
+            path: path.as_ref().to_path_buf(),
+            tape: Arc::new(Mutex::new(Tape::default())),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cassette> {
+        let mut contents = String::new();
+        File::open(path.as_ref())
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .chain_err(|| format!("Could not read cassette {}", path.as_ref().display()))?;
+        let file: CassetteFile =
+            serde_json::from_str(&contents).chain_err(|| "Failed to deserialize cassette")?;
+
+        let cassette = Cassette::new(path);
+        {
+            let mut tape = cassette.tape.lock().unwrap();
+            tape.played = vec![false; file.interactions.len()];
+            tape.interactions = file.interactions;
+        }
+        Ok(cassette)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let tape = self.tape.lock().unwrap();
+        let file = CassetteFile {
+            interactions: tape.interactions.clone(),
+        };
+        let contents =
+            serde_json::to_string_pretty(&file).chain_err(|| "Failed to serialize cassette")?;
+
+        File::create(&self.path)
+            .and_then(|mut f| f.write_all(contents.as_bytes()))
+            .chain_err(|| format!("Could not write cassette {}", self.path.display()))
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.tape.lock().unwrap().interactions.clone()
+    }
+
+    // Add an interaction and save the cassette, so that recordings survive
+    // a test panicking part way through
+    pub fn record(&self, request: Request, response: Response) -> Result<()> {
+        self.push(Interaction {
+            request,
+            response,
+            streamed: None,
+        })
+    }
+
+    // Add a streamed interaction, along with what was passed to its sink
+    pub fn record_stream(
+        &self,
+        request: Request,
+        response: Response,
+        streamed: &[u8],
+    ) -> Result<()> {
+        self.push(Interaction {
+            request,
+            response,
+            streamed: Some(base64::encode(streamed)),
+        })
+    }
+
+    fn push(&self, interaction: Interaction) -> Result<()> {
+        {
+            let mut tape = self.tape.lock().unwrap();
+            tape.interactions.push(interaction);
+            tape.played.push(false);
+        }
+        self.save()
+    }
+
+    // Find the response for a request with the same method, path and body.
+    // Identical requests are answered in the order they were recorded, with
+    // the last answer repeated once they have all been played.
+    pub fn play(&self, request: &Request) -> Result<Response> {
+        self.find(request).map(|i| i.response)
+    }
+
+    // Answer a streamed request, passing its recorded body to `sink`
+    pub fn play_stream(&self, request: &Request, sink: &mut dyn Write) -> Result<Response> {
+        let interaction = self.find(request)?;
+        let streamed = match interaction.streamed {
+            Some(ref streamed) => {
+                base64::decode(streamed).chain_err(|| "Failed to decode streamed body")?
+            }
+            None => bail!(
+                "{} {} was not recorded as a stream in {}",
+                request.method,
+                request.path,
+                self.path.display()
+            ),
+        };
+        sink.write_all(&streamed)
+            .chain_err(|| "Could not write streamed body")?;
+        Ok(interaction.response)
+    }
+
+    fn find(&self, request: &Request) -> Result<Interaction> {
+        let mut tape = self.tape.lock().unwrap();
+        let matching: Vec<usize> = tape
+            .interactions
+            .iter()
+            .enumerate()
+            .filter(|&(_, i)| {
+                i.request.method == request.method
+                    && i.request.path == request.path
+                    && i.request.body == request.body
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let idx = match matching.iter().find(|&&idx| !tape.played[idx]) {
+            Some(&idx) => idx,
+            None => match matching.last() {
+                Some(&idx) => idx,
+                None => bail!(
+                    "No recorded interaction for {} {} in {}",
+                    request.method,
+                    request.path,
+                    self.path.display()
+                ),
+            },
+        };
+
+        tape.played[idx] = true;
+        Ok(tape.interactions[idx].clone())
+    }
+}
+
+// Wraps another stream, recording each exchange into a cassette
+pub struct RecordingStream {
+    inner: Box<dyn HttpStream>,
+    cassette: Cassette,
+}
+
+impl RecordingStream {
+    pub fn new(inner: Box<dyn HttpStream>, cassette: Cassette) -> RecordingStream {
+        RecordingStream { inner, cassette }
+    }
+}
+
+impl HttpStream for RecordingStream {
+    fn request(&mut self, req: Request) -> Result<Response> {
+        let recorded = req.clone();
+        let response = self.inner.request(req)?;
+
+        self.cassette
+            .record(recorded, response.clone())
+            .chain_err(|| "Could not record engine response")?;
+
+        Ok(response)
+    }
+
+    // The response body is kept as it is passed on to `sink`, so it is held
+    // in memory until the exchange has been recorded
+    fn stream(
+        &mut self,
+        req: Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<Response> {
+        let recorded = req.clone();
+        let mut tee = Tee {
+            sink,
+            copy: Vec::new(),
+        };
+        let response = self.inner.stream(req, body, &mut tee)?;
+
+        self.cassette
+            .record_stream(recorded, response.clone(), &tee.copy)
+            .chain_err(|| "Could not record engine response")?;
+
+        Ok(response)
+    }
+}
+
+// Passes writes on to a sink, keeping a copy of what it accepted
+struct Tee<'a> {
+    sink: &'a mut dyn Write,
+    copy: Vec<u8>,
+}
+
+impl<'a> Write for Tee<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        self.copy.extend_from_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+// Answers requests from a cassette without connecting anywhere
+pub struct ReplayStream {
+    cassette: Cassette,
+}
+
+impl HttpStream for ReplayStream {
+    fn request(&mut self, req: Request) -> Result<Response> {
+        self.cassette.play(&req)
+    }
+
+    // Bodies streamed to the engine were not recorded, so are not read
+    fn stream(
+        &mut self,
+        req: Request,
+        _body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<Response> {
+        self.cassette.play_stream(&req, sink)
+    }
+}
+
+// Connects to the client's cassette for the Replay backend
+pub struct ReplayConnector;
+
+impl Connector for ReplayConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        let cassette = client
+            .cassette
+            .clone()
+            .chain_err(|| "Replay backend chosen with no cassette")?;
+
+        Ok(Box::new(ReplayStream { cassette }))
+    }
+}
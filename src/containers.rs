@@ -3,8 +3,10 @@ use std::collections::HashMap;
 use serde_json;
 
 use errors::*;
-use network::get;
+use network::{expect_status, get, post};
+use reference::Reference;
 use types::Client;
+use filters::{ContainerFilters, ContainerPruneFilters};
 use queryparameters::{generate_path, QueryParameters};
 
 #[derive(Deserialize, Debug)]
@@ -80,21 +82,401 @@ pub struct Container {
     pub mounts: Vec<Mount>,
 }
 
+impl Container {
+    // The image the container was created from, or None when it was created
+    // from an image ID rather than a name
+    pub fn image_reference(&self) -> Option<Reference> {
+        if self.image.starts_with("sha256:") {
+            return None;
+        }
+        Reference::parse(&self.image).ok()
+    }
+}
+
+// Typed options for listing containers, rendered to the query parameters
+// that `get_containers` accepts
+#[derive(Debug, Clone, Default)]
+pub struct ListContainersOptions {
+    all: bool,
+    limit: Option<u64>,
+    size: bool,
+    filters: ContainerFilters,
+}
+
+impl ListContainersOptions {
+    pub fn new() -> ListContainersOptions {
+        ListContainersOptions::default()
+    }
+
+    // Include stopped containers, rather than only running ones
+    pub fn all(mut self, all: bool) -> ListContainersOptions {
+        self.all = all;
+        self
+    }
+
+    // Only return the most recently created containers
+    pub fn limit(mut self, limit: u64) -> ListContainersOptions {
+        self.limit = Some(limit);
+        self
+    }
+
+    // Populate the `size_rw` and `size_root_fs` fields
+    pub fn size(mut self, size: bool) -> ListContainersOptions {
+        self.size = size;
+        self
+    }
+
+    // Only return containers created after the given container
+    pub fn since(mut self, container: &str) -> ListContainersOptions {
+        self.filters = self.filters.since(container);
+        self
+    }
+
+    // Only return containers created before the given container
+    pub fn before(mut self, container: &str) -> ListContainersOptions {
+        self.filters = self.filters.before(container);
+        self
+    }
+
+    // Add filters to those set so far, including by `since` and `before`
+    pub fn filters(mut self, filters: ContainerFilters) -> ListContainersOptions {
+        self.filters = self.filters.merge(&filters);
+        self
+    }
+
+    pub fn to_query_parameters(&self) -> QueryParameters {
+        let mut params = QueryParameters::new();
+        if self.all {
+            params.add("all", true);
+        }
+        if let Some(limit) = self.limit {
+            params.add("limit", limit);
+        }
+        if self.size {
+            params.add("size", true);
+        }
+        params.add_filters(&self.filters);
+        params
+    }
+}
+
 pub fn get_containers_parse(json: &str) -> Result<Vec<Container>> {
     serde_json::from_str(json).chain_err(|| "Failed to deserialize get_containers response")
 }
 
 pub fn get_containers(
-    client: Client,
+    client: &Client,
     args: Option<&mut QueryParameters>,
 ) -> Result<Vec<Container>> {
     let path = generate_path("/containers/json", args);
 
-    let response = get(client, &path).chain_err(|| "Failed to get container list")?;
+    let response = get(client, &path)?;
+    expect_status(&response, &path, 200)?;
+
+    get_containers_parse(&response.body)
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestartPolicy {
+    // One of "", "no", "always", "unless-stopped" or "on-failure"
+    pub name: String,
+    // Only used with the "on-failure" policy
+    pub maximum_retry_count: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct UpdateConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_shares: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_period: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_quota: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpuset_cpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpuset_mems: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nano_cpus: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_swap: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_reservation: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart_policy: Option<RestartPolicy>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct UpdateResponse {
+    pub warnings: Option<Vec<String>>,
+}
+
+pub fn update_parse(json: &str) -> Result<Vec<String>> {
+    let response: UpdateResponse =
+        serde_json::from_str(json).chain_err(|| "Failed to deserialize update response")?;
+
+    Ok(response.warnings.unwrap_or_default())
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PruneResponse {
+    pub containers_deleted: Option<Vec<String>>,
+    pub space_reclaimed: u64,
+}
+
+pub fn prune_parse(json: &str) -> Result<PruneResponse> {
+    serde_json::from_str(json).chain_err(|| "Failed to deserialize prune response")
+}
 
-    if response.status_code != 200 {
-        bail!("non-200 response from server");
+// Image configuration to use instead of the container's when committing it.
+// Fields left unset keep the container's values.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommitConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_signal: Option<String>,
+}
+
+// Typed options for creating an image from a container with `commit`
+#[derive(Debug, Clone)]
+pub struct CommitOptions {
+    image: Option<Reference>,
+    comment: Option<String>,
+    author: Option<String>,
+    pause: bool,
+    changes: Vec<String>,
+    config: Option<CommitConfig>,
+}
+
+impl CommitOptions {
+    pub fn new() -> CommitOptions {
+        CommitOptions {
+            image: None,
+            comment: None,
+            author: None,
+            pause: true,
+            changes: Vec::new(),
+            config: None,
+        }
     }
 
-    get_containers_parse(&response.body)
+    // The repository and tag to give the committed image
+    pub fn image(mut self, image: Reference) -> CommitOptions {
+        self.image = Some(image);
+        self
+    }
+
+    pub fn comment(mut self, comment: &str) -> CommitOptions {
+        self.comment = Some(String::from(comment));
+        self
+    }
+
+    pub fn author(mut self, author: &str) -> CommitOptions {
+        self.author = Some(String::from(author));
+        self
+    }
+
+    // Pause the container while committing it, which is the default
+    pub fn pause(mut self, pause: bool) -> CommitOptions {
+        self.pause = pause;
+        self
+    }
+
+    // Apply a Dockerfile instruction, such as `ENV DEBUG=1`, to the image
+    pub fn change(mut self, change: &str) -> CommitOptions {
+        self.changes.push(String::from(change));
+        self
+    }
+
+    pub fn config(mut self, config: CommitConfig) -> CommitOptions {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn to_query_parameters(&self, container: &str) -> QueryParameters {
+        let mut params = QueryParameters::new();
+        params.add("container", container);
+        if let Some(ref image) = self.image {
+            params.add_repository(image);
+        }
+        if let Some(ref comment) = self.comment {
+            params.add("comment", comment);
+        }
+        if let Some(ref author) = self.author {
+            params.add("author", author);
+        }
+        params.add("pause", self.pause);
+        for change in &self.changes {
+            params.add("changes", change);
+        }
+        params
+    }
+}
+
+impl Default for CommitOptions {
+    fn default() -> CommitOptions {
+        CommitOptions::new()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommitResponse {
+    pub id: String,
+}
+
+// Container endpoints, as returned by `Client::containers`
+pub struct Containers<'a> {
+    client: &'a Client,
+}
+
+impl<'a> Containers<'a> {
+    pub fn new(client: &'a Client) -> Containers<'a> {
+        Containers { client }
+    }
+
+    pub fn list(&self, options: &ListContainersOptions) -> Result<Vec<Container>> {
+        get_containers(self.client, Some(&mut options.to_query_parameters()))
+    }
+
+    // Update the resource limits and restart policy of a container without
+    // restarting it, returning any warnings the engine generated
+    pub fn update(&self, id: &str, config: &UpdateConfig) -> Result<Vec<String>> {
+        let path = format!("/containers/{}/update", id);
+        let body =
+            serde_json::to_string(config).chain_err(|| "Failed to serialize update config")?;
+
+        let response = post(self.client, &path, &body)?;
+        expect_status(&response, &path, 200)?;
+
+        update_parse(&response.body)
+    }
+
+    pub fn rename(&self, id: &str, name: &str) -> Result<()> {
+        let mut args = QueryParameters::new();
+        args.add("name", name);
+        let path = generate_path(&format!("/containers/{}/rename", id), Some(&mut args));
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 204)?;
+
+        Ok(())
+    }
+
+    pub fn pause(&self, id: &str) -> Result<()> {
+        let path = format!("/containers/{}/pause", id);
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 204)?;
+
+        Ok(())
+    }
+
+    pub fn unpause(&self, id: &str) -> Result<()> {
+        let path = format!("/containers/{}/unpause", id);
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 204)?;
+
+        Ok(())
+    }
+
+    // Resize the TTY of a container, with dimensions given in characters
+    pub fn resize(&self, id: &str, height: u32, width: u32) -> Result<()> {
+        let mut args = QueryParameters::new();
+        args.add("h", height);
+        args.add("w", width);
+        let path = generate_path(&format!("/containers/{}/resize", id), Some(&mut args));
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 200)?;
+
+        Ok(())
+    }
+
+    // Create an image from a container's filesystem, returning its ID
+    pub fn commit(&self, id: &str, options: &CommitOptions) -> Result<String> {
+        let path = generate_path("/commit", Some(&mut options.to_query_parameters(id)));
+        let body = match options.config {
+            Some(ref config) => {
+                serde_json::to_string(config).chain_err(|| "Failed to serialize commit config")?
+            }
+            None => String::new(),
+        };
+
+        let response = post(self.client, &path, &body)?;
+        expect_status(&response, &path, 201)?;
+
+        let commit: CommitResponse = serde_json::from_str(&response.body)
+            .chain_err(|| "Failed to deserialize commit response")?;
+        Ok(commit.id)
+    }
+
+    // Remove all stopped containers, optionally only those matching the
+    // given filters
+    pub fn prune(&self, filters: Option<&ContainerPruneFilters>) -> Result<PruneResponse> {
+        let mut args = filters.map(|f| {
+            let mut args = QueryParameters::new();
+            args.add_filters(f);
+            args
+        });
+        let path = generate_path("/containers/prune", args.as_mut());
+
+        let response = post(self.client, &path, "")?;
+        expect_status(&response, &path, 200)?;
+
+        prune_parse(&response.body)
+    }
+}
+
+pub fn list(client: &Client, options: &ListContainersOptions) -> Result<Vec<Container>> {
+    client.containers().list(options)
+}
+
+pub fn update(client: &Client, id: &str, config: &UpdateConfig) -> Result<Vec<String>> {
+    client.containers().update(id, config)
+}
+
+pub fn rename(client: &Client, id: &str, name: &str) -> Result<()> {
+    client.containers().rename(id, name)
+}
+
+pub fn pause(client: &Client, id: &str) -> Result<()> {
+    client.containers().pause(id)
+}
+
+pub fn unpause(client: &Client, id: &str) -> Result<()> {
+    client.containers().unpause(id)
+}
+
+pub fn resize(client: &Client, id: &str, height: u32, width: u32) -> Result<()> {
+    client.containers().resize(id, height, width)
+}
+
+pub fn prune(client: &Client, filters: Option<&ContainerPruneFilters>) -> Result<PruneResponse> {
+    client.containers().prune(filters)
+}
+
+pub fn commit(client: &Client, id: &str, options: &CommitOptions) -> Result<String> {
+    client.containers().commit(id, options)
 }
@@ -0,0 +1,509 @@
+// Packing a directory into a tar archive to send as a build context,
+// leaving out what its `.dockerignore` excludes the way the Docker CLI does.
+// The archive is produced as it is read, so contexts are never held in
+// memory, and it can be passed straight to `build::image`.
+
+use std::cmp;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use regex::{self, Regex};
+
+use errors::*;
+
+const BLOCK_SIZE: usize = 512;
+
+// The largest size the ustar header's 11 octal digits hold
+const MAX_OCTAL_SIZE: u64 = 0o77_777_777_777;
+
+const REGULAR: u8 = b'0';
+const SYMLINK: u8 = b'2';
+const DIRECTORY: u8 = b'5';
+const PAX_HEADER: u8 = b'x';
+
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    pattern: String,
+    // Patterns starting with `!` bring back paths which were excluded
+    exception: bool,
+    regex: Regex,
+}
+
+// The patterns of a `.dockerignore` file, matched against paths relative to
+// the context directory. Later patterns take precedence over earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct DockerIgnore {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl DockerIgnore {
+    pub fn new() -> DockerIgnore {
+        DockerIgnore::default()
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DockerIgnore> {
+        let contents = fs::read_to_string(path).chain_err(|| "Could not read .dockerignore")?;
+        DockerIgnore::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<DockerIgnore> {
+        let mut ignore = DockerIgnore::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = if number == 0 {
+                line.trim_start_matches('\u{feff}')
+            } else {
+                line
+            };
+            // Only a `#` at the very start of a line begins a comment
+            if line.starts_with('#') {
+                continue;
+            }
+            let line = line.trim();
+            if !line.is_empty() {
+                ignore.add(line)?;
+            }
+        }
+        Ok(ignore)
+    }
+
+    // Add a pattern as if it were the last line of the file
+    pub fn add(&mut self, pattern: &str) -> Result<()> {
+        let (exception, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern.trim()),
+            None => (false, pattern.trim()),
+        };
+        if pattern.is_empty() {
+            bail!("Illegal exclusion pattern: !");
+        }
+
+        let mut pattern = clean(pattern);
+        if pattern.len() > 1 && pattern.starts_with('/') {
+            pattern.remove(0);
+        }
+        let regex = compile(&pattern)?;
+
+        self.patterns.push(IgnorePattern {
+            pattern,
+            exception,
+            regex,
+        });
+        Ok(())
+    }
+
+    // Whether a path, or one of the directories it is in, is excluded
+    pub fn excludes(&self, path: &str) -> bool {
+        let parents: Vec<&str> = match path.rfind('/') {
+            Some(end) => path[..end].split('/').collect(),
+            None => Vec::new(),
+        };
+
+        let mut excluded = false;
+        for pattern in &self.patterns {
+            // Exclusions can't change an excluded path, and exceptions
+            // can't change an included one
+            if pattern.exception != excluded {
+                continue;
+            }
+            let matched = pattern.regex.is_match(path)
+                || (1..=parents.len()).any(|i| pattern.regex.is_match(&parents[..i].join("/")));
+            if matched {
+                excluded = !pattern.exception;
+            }
+        }
+        excluded
+    }
+
+    // Whether an excluded directory has to be walked anyway, because an
+    // exception names something inside it
+    fn has_exceptions_in(&self, dir: &str) -> bool {
+        let dir = format!("{}/", dir);
+        self.patterns
+            .iter()
+            .any(|p| p.exception && format!("{}/", p.pattern).starts_with(&dir))
+    }
+}
+
+// Lexically clean a path as Go's `filepath.Clean` does, which is what the
+// CLI applies to each pattern
+fn clean(path: &str) -> String {
+    let rooted = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => match parts.last() {
+                Some(&last) if last != ".." => {
+                    parts.pop();
+                }
+                _ if rooted => {}
+                _ => parts.push(".."),
+            },
+            part => parts.push(part),
+        }
+    }
+
+    let cleaned = parts.join("/");
+    match (rooted, cleaned.is_empty()) {
+        (true, _) => format!("/{}", cleaned),
+        (false, true) => String::from("."),
+        (false, false) => cleaned,
+    }
+}
+
+// Translate a pattern to a regex. `*` and `?` don't match `/`, while `**`
+// matches any number of directories, including none.
+fn compile(pattern: &str) -> Result<Regex> {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` is the same as `**`
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                }
+                if chars.peek().is_none() {
+                    regex.push_str(".*");
+                } else {
+                    regex.push_str("(.*/)?");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '\\' => match chars.next() {
+                Some(escaped) => regex.push_str(&regex::escape(&escaped.to_string())),
+                None => regex.push_str(r"\\"),
+            },
+            // Like the CLI, everything else but `.` and `$` is passed on to
+            // the regex, so that `[a-z]` classes work
+            '.' | '$' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    regex.push('$');
+
+    Regex::new(&regex).chain_err(|| format!("Illegal exclusion pattern: {}", pattern))
+}
+
+// Options for packing a build context with `BuildContext::new`
+#[derive(Debug, Clone)]
+pub struct ContextOptions {
+    dockerfile: String,
+    zero_mtimes: bool,
+}
+
+impl ContextOptions {
+    pub fn new() -> ContextOptions {
+        ContextOptions::default()
+    }
+
+    // The path of the Dockerfile within the context, which is sent even
+    // when `.dockerignore` excludes it
+    pub fn dockerfile(mut self, dockerfile: &str) -> ContextOptions {
+        self.dockerfile = String::from(dockerfile);
+        self
+    }
+
+    // Set every modification time to zero, so that the same files always
+    // give the same archive
+    pub fn zero_mtimes(mut self, zero_mtimes: bool) -> ContextOptions {
+        self.zero_mtimes = zero_mtimes;
+        self
+    }
+}
+
+impl Default for ContextOptions {
+    fn default() -> ContextOptions {
+        ContextOptions {
+            dockerfile: String::from("Dockerfile"),
+            zero_mtimes: false,
+        }
+    }
+}
+
+// A file whose contents are being read into the archive
+struct OpenFile {
+    file: File,
+    path: PathBuf,
+    remaining: u64,
+    padding: usize,
+}
+
+// A tar archive of a directory, read as it is walked. Entries are in the
+// order the CLI sends them, owned by root, and keep their modes and
+// symlinks. Names which don't fit a ustar header use PAX records.
+pub struct BuildContext {
+    root: PathBuf,
+    ignore: DockerIgnore,
+    zero_mtimes: bool,
+    // The entries left in each directory being walked, last first
+    pending: Vec<Vec<String>>,
+    // Headers and padding waiting to be read
+    buffer: Vec<u8>,
+    position: usize,
+    file: Option<OpenFile>,
+    finished: bool,
+}
+
+impl BuildContext {
+    pub fn new<P: AsRef<Path>>(dir: P, options: &ContextOptions) -> Result<BuildContext> {
+        let root = dir.as_ref().to_path_buf();
+        let ignore_file = root.join(".dockerignore");
+        let mut ignore = if ignore_file.exists() {
+            DockerIgnore::from_file(&ignore_file)?
+        } else {
+            DockerIgnore::new()
+        };
+
+        // The engine needs these, whatever the patterns say
+        if ignore.excludes(".dockerignore") {
+            ignore.add("!.dockerignore")?;
+        }
+        let dockerfile = clean(&options.dockerfile);
+        if ignore.excludes(&dockerfile) {
+            ignore.add(&format!("!{}", dockerfile))?;
+        }
+
+        let entries = list(&root, "").chain_err(|| "Could not read build context")?;
+        Ok(BuildContext {
+            root,
+            ignore,
+            zero_mtimes: options.zero_mtimes,
+            pending: vec![entries],
+            buffer: Vec::new(),
+            position: 0,
+            file: None,
+            finished: false,
+        })
+    }
+
+    // Queue the header of the next entry, returning false once the walk
+    // is done
+    fn next_entry(&mut self) -> io::Result<bool> {
+        loop {
+            let relative = match self.pending.last_mut() {
+                Some(entries) => entries.pop(),
+                None => return Ok(false),
+            };
+            let relative = match relative {
+                Some(relative) => relative,
+                None => {
+                    self.pending.pop();
+                    continue;
+                }
+            };
+
+            let path = self.root.join(&relative);
+            let metadata = fs::symlink_metadata(&path).map_err(|e| path_error(&path, e))?;
+            let file_type = metadata.file_type();
+            let excluded = self.ignore.excludes(&relative);
+
+            if file_type.is_dir() {
+                if excluded && !self.ignore.has_exceptions_in(&relative) {
+                    continue;
+                }
+                self.pending.push(list(&self.root, &relative)?);
+                if excluded {
+                    continue;
+                }
+                let name = format!("{}/", relative);
+                self.queue_header(&name, &metadata, DIRECTORY, 0, "");
+            } else if excluded {
+                continue;
+            } else if file_type.is_symlink() {
+                let target = fs::read_link(&path).map_err(|e| path_error(&path, e))?;
+                let target = utf8(&path, target.to_str())?;
+                self.queue_header(&relative, &metadata, SYMLINK, 0, target);
+            } else if file_type.is_file() {
+                let file = File::open(&path).map_err(|e| path_error(&path, e))?;
+                let size = metadata.len();
+                self.queue_header(&relative, &metadata, REGULAR, size, "");
+                self.file = Some(OpenFile {
+                    file,
+                    path,
+                    remaining: size,
+                    padding: padding(size),
+                });
+            } else {
+                // Sockets, pipes and devices are no use to a build
+                continue;
+            }
+            return Ok(true);
+        }
+    }
+
+    fn queue_header(&mut self, name: &str, metadata: &Metadata, kind: u8, size: u64, link: &str) {
+        let mode = metadata.permissions().mode() & 0o7777;
+        let mtime = if self.zero_mtimes {
+            0
+        } else {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs())
+        };
+
+        let mut records = String::new();
+        if name.len() > 100 {
+            records.push_str(&pax_record("path", name));
+        }
+        if link.len() > 100 {
+            records.push_str(&pax_record("linkpath", link));
+        }
+        if size > MAX_OCTAL_SIZE {
+            records.push_str(&pax_record("size", &size.to_string()));
+        }
+        if !records.is_empty() {
+            let length = records.len() as u64;
+            let header = header("././@PaxHeader", 0o644, mtime, length, PAX_HEADER, "");
+            self.buffer.extend_from_slice(&header);
+            self.buffer.extend_from_slice(records.as_bytes());
+            self.buffer.resize(self.buffer.len() + padding(length), 0);
+        }
+
+        let header = header(name, mode, mtime, size, kind, link);
+        self.buffer.extend_from_slice(&header);
+    }
+}
+
+impl Read for BuildContext {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.position < self.buffer.len() {
+                let count = cmp::min(buf.len(), self.buffer.len() - self.position);
+                buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+                self.position += count;
+                return Ok(count);
+            }
+            self.buffer.clear();
+            self.position = 0;
+
+            if let Some(ref mut open) = self.file {
+                if open.remaining > 0 {
+                    let limit = cmp::min(buf.len() as u64, open.remaining) as usize;
+                    let count = open.file.read(&mut buf[..limit])?;
+                    if count == 0 {
+                        let message = "file shrank while the build context was being read";
+                        return Err(path_error(
+                            &open.path,
+                            io::Error::new(io::ErrorKind::UnexpectedEof, message),
+                        ));
+                    }
+                    open.remaining -= count as u64;
+                    return Ok(count);
+                }
+            }
+            if let Some(open) = self.file.take() {
+                self.buffer.resize(open.padding, 0);
+                continue;
+            }
+
+            if !self.next_entry()? {
+                if self.finished {
+                    return Ok(0);
+                }
+                // The archive ends with two empty blocks
+                self.finished = true;
+                self.buffer.resize(2 * BLOCK_SIZE, 0);
+            }
+        }
+    }
+}
+
+// The entries of a directory in the context, relative to its root and
+// sorted so that popping them gives lexical order
+fn list(root: &Path, dir: &str) -> io::Result<Vec<String>> {
+    let path = root.join(dir);
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&path).map_err(|e| path_error(&path, e))? {
+        let entry = entry.map_err(|e| path_error(&path, e))?;
+        let name = entry.file_name();
+        let name = utf8(&entry.path(), name.to_str())?;
+        if dir.is_empty() {
+            entries.push(String::from(name));
+        } else {
+            entries.push(format!("{}/{}", dir, name));
+        }
+    }
+    entries.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(entries)
+}
+
+fn utf8<'a>(path: &Path, name: Option<&'a str>) -> io::Result<&'a str> {
+    name.ok_or_else(|| {
+        let error = io::Error::new(io::ErrorKind::InvalidData, "name is not valid UTF-8");
+        path_error(path, error)
+    })
+}
+
+fn path_error(path: &Path, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{}: {}", path.display(), error))
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+// A PAX record, which starts with its own length
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest;
+    loop {
+        let next = rest + length.to_string().len();
+        if next == length {
+            break;
+        }
+        length = next;
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+// A ustar header. Fields which don't fit are truncated, for a PAX record
+// in front of the header to give in full.
+fn header(name: &str, mode: u32, mtime: u64, size: u64, kind: u8, link: &str) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+    text(&mut header[0..100], name);
+    octal(&mut header[100..108], u64::from(mode));
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(
+        &mut header[124..136],
+        if size > MAX_OCTAL_SIZE { 0 } else { size },
+    );
+    octal(&mut header[136..148], cmp::min(mtime, MAX_OCTAL_SIZE));
+    header[156] = kind;
+    text(&mut header[157..257], link);
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    octal(&mut header[329..337], 0);
+    octal(&mut header[337..345], 0);
+
+    // The checksum is taken with its own field filled with spaces
+    for byte in &mut header[148..156] {
+        *byte = b' ';
+    }
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+fn text(field: &mut [u8], value: &str) {
+    let length = cmp::min(field.len(), value.len());
+    field[..length].copy_from_slice(&value.as_bytes()[..length]);
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
@@ -0,0 +1,224 @@
+// Finding the credentials for a registry the way the docker CLI does, from
+// `~/.docker/config.json`. Credentials may be stored in the file itself,
+// or kept by a credential helper, a `docker-credential-<name>` program
+// which is asked for them over stdin and stdout.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{ErrorKind as IoErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use base64;
+use serde_json;
+
+use auth::RegistryAuth;
+use errors::*;
+use reference::{AsReference, Reference};
+
+// The address the docker CLI stores Docker Hub credentials under
+pub const DOCKER_HUB_ADDRESS: &str = "https://index.docker.io/v1/";
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AuthEntry {
+    // base64 of `username:password`
+    pub auth: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub identitytoken: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerConfig {
+    #[serde(default)]
+    pub auths: HashMap<String, AuthEntry>,
+    // The helper which holds credentials for every registry
+    pub creds_store: Option<String>,
+    // Helpers for particular registries, which take precedence over
+    // `creds_store`
+    #[serde(default)]
+    pub cred_helpers: HashMap<String, String>,
+    // Where to look for helpers, instead of the PATH
+    #[serde(skip)]
+    helper_dir: Option<PathBuf>,
+}
+
+// What a credential helper returns from `get`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperCredentials {
+    username: String,
+    secret: String,
+}
+
+impl DockerConfig {
+    // Read the config from DOCKER_CONFIG, or ~/.docker, as the docker CLI
+    // does. A missing file is the same as an empty one.
+    pub fn load() -> Result<DockerConfig> {
+        let dir = env::var("DOCKER_CONFIG")
+            .ok()
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(env::var("HOME").unwrap_or_default()).join(".docker"));
+
+        match fs::read_to_string(dir.join("config.json")) {
+            Ok(json) => DockerConfig::parse(&json),
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => Ok(DockerConfig::default()),
+            Err(e) => Err(Error::with_chain(e, "Could not read docker config")),
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<DockerConfig> {
+        let json = fs::read_to_string(path).chain_err(|| "Could not read docker config")?;
+        DockerConfig::parse(&json)
+    }
+
+    pub fn parse(json: &str) -> Result<DockerConfig> {
+        serde_json::from_str(json).chain_err(|| "Failed to deserialize docker config")
+    }
+
+    // Run credential helpers from `dir` rather than finding them on the PATH
+    pub fn helper_dir<P: AsRef<Path>>(mut self, dir: P) -> DockerConfig {
+        self.helper_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // The credentials for a registry, such as `registry.example.com:5000`
+    // or `docker.io`, or None when there are none stored
+    pub fn lookup(&self, registry: &str) -> Result<Option<RegistryAuth>> {
+        let registry = normalize_registry(registry);
+
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|&(host, _)| normalize_registry(host) == registry)
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+        if let Some(helper) = helper {
+            return self.run_helper(helper, &registry);
+        }
+
+        match self
+            .auths
+            .iter()
+            .find(|&(host, _)| normalize_registry(host) == registry)
+        {
+            Some((_, entry)) => entry_auth(entry, &registry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // The credentials for the registry an image is stored in
+    pub fn lookup_image<R: AsReference + ?Sized>(&self, image: &R) -> Result<Option<RegistryAuth>> {
+        self.lookup(image.to_reference()?.domain())
+    }
+
+    fn run_helper(&self, helper: &str, registry: &str) -> Result<Option<RegistryAuth>> {
+        let name = format!("docker-credential-{}", helper);
+        let program = match self.helper_dir {
+            Some(ref dir) => dir.join(&name),
+            None => PathBuf::from(&name),
+        };
+
+        let mut child = Command::new(program)
+            .arg("get")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .chain_err(|| format!("Could not run {}", name))?;
+        // Piped above
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(server_address(registry).as_bytes())
+            .chain_err(|| format!("Could not write to {}", name))?;
+        let output = child
+            .wait_with_output()
+            .chain_err(|| format!("Could not run {}", name))?;
+
+        if !output.status.success() {
+            // Helpers report missing credentials on stdout
+            let message = String::from_utf8_lossy(&output.stdout);
+            if message.contains("credentials not found") {
+                return Ok(None);
+            }
+            bail!("{} failed: {}", name, message.trim());
+        }
+
+        let creds: HelperCredentials = serde_json::from_slice(&output.stdout)
+            .chain_err(|| format!("Failed to deserialize {} response", name))?;
+        // Helpers store identity tokens with this username
+        if creds.username == "<token>" {
+            return Ok(Some(RegistryAuth::identity_token(
+                &creds.secret,
+                &server_address(registry),
+            )));
+        }
+        Ok(Some(RegistryAuth::password(
+            &creds.username,
+            &creds.secret,
+            &server_address(registry),
+        )))
+    }
+}
+
+fn entry_auth(entry: &AuthEntry, registry: &str) -> Result<RegistryAuth> {
+    let mut auth = RegistryAuth {
+        username: entry.username.clone(),
+        password: entry.password.clone(),
+        server_address: Some(server_address(registry)),
+        identity_token: entry.identitytoken.clone(),
+    };
+
+    if let Some(encoded) = entry.auth.as_ref().filter(|a| !a.is_empty()) {
+        let decoded = base64::decode(encoded)
+            .ok()
+            .and_then(|d| String::from_utf8(d).ok())
+            .chain_err(|| format!("Could not decode credentials for {}", registry))?;
+        let mut parts = decoded.splitn(2, ':');
+        auth.username = parts.next().map(String::from);
+        auth.password =
+            Some(String::from(parts.next().chain_err(|| {
+                format!("Invalid credentials for {}", registry)
+            })?));
+    }
+
+    Ok(auth)
+}
+
+// Reduce a registry address to its host and port, so that addresses
+// written differently match. Docker Hub goes by several names, which all
+// become `docker.io`.
+pub fn normalize_registry(registry: &str) -> String {
+    let host = registry
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .split('/')
+        .next()
+        .unwrap_or("")
+        .to_lowercase();
+
+    match host.as_str() {
+        "" | "index.docker.io" | "registry-1.docker.io" | "registry.hub.docker.com" => {
+            String::from("docker.io")
+        }
+        _ => host,
+    }
+}
+
+// The registry an image name refers to, such as `docker.io` for `busybox`
+pub fn registry_for_image(image: &str) -> Result<String> {
+    Ok(normalize_registry(Reference::parse(image)?.domain()))
+}
+
+// The address credentials are stored and sent under for a registry
+fn server_address(registry: &str) -> String {
+    match registry {
+        "docker.io" => String::from(DOCKER_HUB_ADDRESS),
+        _ => String::from(registry),
+    }
+}
@@ -0,0 +1,67 @@
+// Asking the engine about an image in its registry, without pulling it
+
+use serde_json;
+
+use auth;
+use auth::RegistryAuth;
+use errors::*;
+use network::{expect_status, gen_request, perform_client_request};
+pub use platform::Platform;
+use reference::AsReference;
+use types::Client;
+
+// The manifest a reference resolves to in the registry
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: i64,
+    #[serde(default)]
+    pub urls: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct DistributionInspect {
+    pub descriptor: Descriptor,
+    #[serde(default)]
+    pub platforms: Vec<Platform>,
+}
+
+// Distribution endpoints, as returned by `Client::distribution`
+pub struct Distribution<'a> {
+    client: &'a Client,
+}
+
+impl<'a> Distribution<'a> {
+    pub fn new(client: &'a Client) -> Distribution<'a> {
+        Distribution { client }
+    }
+
+    // Look up the digest and platforms of an image in its registry, which
+    // the engine fetches with the given credentials
+    pub fn inspect<R: AsReference + ?Sized>(
+        &self,
+        image: &R,
+        auth: Option<&RegistryAuth>,
+    ) -> Result<DistributionInspect> {
+        let path = format!("/distribution/{}/json", image.to_reference()?.familiar());
+
+        let mut req = gen_request("GET", &path, None);
+        auth::set_header(&mut req, auth);
+        let response = perform_client_request(self.client, req)?;
+        expect_status(&response, &path, 200)?;
+
+        serde_json::from_str(&response.body)
+            .chain_err(|| "Failed to deserialize distribution response")
+    }
+}
+
+pub fn inspect<R: AsReference + ?Sized>(
+    client: &Client,
+    image: &R,
+    auth: Option<&RegistryAuth>,
+) -> Result<DistributionInspect> {
+    client.distribution().inspect(image, auth)
+}
@@ -1,6 +1,8 @@
+use auth::RegistryAuth;
 use errors::*;
+use platform::Platform;
 use types::Client;
-use network::get;
+use network::{expect_status, get, post};
 
 use serde_json;
 
@@ -19,29 +21,119 @@ pub struct Version {
     pub build_time: String,
 }
 
-pub fn version(client: Client) -> Result<Version> {
-    let response = get(client, "/version").chain_err(|| "Failed to get engine version")?;
+// System-wide information about the engine and the machine it runs on
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct Info {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: String,
+    pub name: String,
+    pub server_version: String,
+    // The kernel's name for the architecture, such as `aarch64` or `armv7l`
+    pub architecture: String,
+    #[serde(rename(deserialize = "OSType"))]
+    pub os_type: String,
+    pub operating_system: String,
+    pub kernel_version: String,
+    #[serde(rename(deserialize = "NCPU"))]
+    pub ncpu: u64,
+    pub mem_total: u64,
+    pub driver: String,
+    pub containers: u64,
+    pub images: u64,
+}
 
-    if response.status_code != 200 {
-        bail!("non-200 response from server");
+impl Info {
+    // The platform images need to be for to run on this engine
+    pub fn platform(&self) -> Platform {
+        Platform::from_engine(&self.os_type, &self.architecture)
     }
+}
 
-    let version: Version =
-        serde_json::from_str(&response.body).chain_err(|| "Failed to deserialize engine response")?;
+// The engine's answer to a successful `auth`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct AuthResponse {
+    pub status: String,
+    // A token to use instead of the password, when the registry gives one
+    #[serde(default)]
+    pub identity_token: Option<String>,
+}
 
-    Ok(version)
+// Engine-wide endpoints, as returned by `Client::engine`
+pub struct Engine<'a> {
+    client: &'a Client,
 }
 
-pub fn ping(client: Client) -> Result<()> {
-    let response = get(client, "/_ping").chain_err(|| "Failed to ping engine")?;
+impl<'a> Engine<'a> {
+    pub fn new(client: &'a Client) -> Engine<'a> {
+        Engine { client }
+    }
 
-    if response.status_code != 200 {
-        bail!("non-200 response from engine");
+    pub fn version(&self) -> Result<Version> {
+        let response = get(self.client, "/version")?;
+        expect_status(&response, "/version", 200)?;
+
+        let version: Version = serde_json::from_str(&response.body)
+            .chain_err(|| "Failed to deserialize engine response")?;
+
+        Ok(version)
     }
 
-    if response.body != "OK" {
-        bail!("Malformed response from engine");
+    pub fn info(&self) -> Result<Info> {
+        let response = get(self.client, "/info")?;
+        expect_status(&response, "/info", 200)?;
+
+        serde_json::from_str(&response.body).chain_err(|| "Failed to deserialize engine response")
     }
 
-    Ok(())
+    pub fn ping(&self) -> Result<()> {
+        let response = get(self.client, "/_ping")?;
+        expect_status(&response, "/_ping", 200)?;
+
+        if response.body != "OK" {
+            bail!("Malformed response from engine");
+        }
+
+        Ok(())
+    }
+
+    // Check credentials against a registry, by having the engine log in to
+    // it. Rejected credentials return a LoginFailed error.
+    pub fn auth(&self, credentials: &RegistryAuth) -> Result<AuthResponse> {
+        let body = serde_json::to_string(credentials)
+            .chain_err(|| "Failed to serialize registry credentials")?;
+        let response = post(self.client, "/auth", &body)?;
+        if response.status_code == 401 {
+            let server = credentials
+                .server_address
+                .as_deref()
+                .unwrap_or("docker.io");
+            bail!(login_error(&response.body, server));
+        }
+        expect_status(&response, "/auth", 200)?;
+
+        let mut auth: AuthResponse = serde_json::from_str(&response.body)
+            .chain_err(|| "Failed to deserialize engine response")?;
+        // The engine sends an empty token when the registry gave none
+        auth.identity_token = auth.identity_token.filter(|t| !t.is_empty());
+
+        Ok(auth)
+    }
+}
+
+pub fn version(client: &Client) -> Result<Version> {
+    client.engine().version()
+}
+
+pub fn info(client: &Client) -> Result<Info> {
+    client.engine().info()
+}
+
+pub fn ping(client: &Client) -> Result<()> {
+    client.engine().ping()
+}
+
+pub fn auth(client: &Client, credentials: &RegistryAuth) -> Result<AuthResponse> {
+    client.engine().auth(credentials)
 }
@@ -2,4 +2,121 @@ error_chain!{
     foreign_links {
         Io(::std::io::Error) #[cfg(unix)];
     }
+
+    // Each engine error kind carries the HTTP status code, the message the
+    // engine gave in its response and the path of the endpoint requested
+    errors {
+        NotModified(status: u16, message: String, path: String) {
+            description("engine resource not modified")
+            display("{} returned {} (not modified): {}", path, status, message)
+        }
+        BadParameter(status: u16, message: String, path: String) {
+            description("bad parameter in engine request")
+            display("{} returned {} (bad parameter): {}", path, status, message)
+        }
+        Unauthorized(status: u16, message: String, path: String) {
+            description("engine request unauthorized")
+            display("{} returned {} (unauthorized): {}", path, status, message)
+        }
+        NotFound(status: u16, message: String, path: String) {
+            description("engine resource not found")
+            display("{} returned {} (not found): {}", path, status, message)
+        }
+        Conflict(status: u16, message: String, path: String) {
+            description("engine request conflicts with current state")
+            display("{} returned {} (conflict): {}", path, status, message)
+        }
+        ServerError(status: u16, message: String, path: String) {
+            description("engine server error")
+            display("{} returned {} (server error): {}", path, status, message)
+        }
+        UnexpectedStatus(status: u16, message: String, path: String) {
+            description("unexpected status from engine")
+            display("{} returned unexpected status {}: {}", path, status, message)
+        }
+        ConnectionFailed(path: String) {
+            description("could not connect to engine")
+            display("Could not connect to engine to request {}", path)
+        }
+        // The registry rejected the credentials given to `engine::auth`,
+        // rather than the engine rejecting the client
+        LoginFailed(message: String, server_address: String) {
+            description("registry rejected credentials")
+            display("Login to {} failed: {}", server_address, message)
+        }
+        // The engine reports errors which happen once it has started
+        // streaming a response as a message in the stream
+        StreamError(message: String, path: String) {
+            description("engine reported an error in its response")
+            display("{} failed: {}", path, message)
+        }
+        // Connecting happens before a request is made, so this carries the
+        // address being connected to instead of a path
+        ConnectTimeout(address: String) {
+            description("timed out connecting to engine")
+            display("Timed out connecting to {}", address)
+        }
+        ReadTimeout(path: String) {
+            description("timed out reading from engine")
+            display("Timed out waiting for the engine to respond to {}", path)
+        }
+        WriteTimeout(path: String) {
+            description("timed out writing to engine")
+            display("Timed out sending request for {} to the engine", path)
+        }
+        DeadlineExceeded(path: String) {
+            description("engine request exceeded deadline")
+            display("{} did not complete within the client's deadline", path)
+        }
+    }
+}
+
+impl Error {
+    // Whether the request failed because one of the client's timeouts ran
+    // out, as opposed to the engine being unreachable or refusing it
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            *self.kind(),
+            ErrorKind::ConnectTimeout(_)
+                | ErrorKind::ReadTimeout(_)
+                | ErrorKind::WriteTimeout(_)
+                | ErrorKind::DeadlineExceeded(_)
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct EngineMessage {
+    message: String,
+}
+
+// The engine sends errors as `{"message": "..."}`, but fall back to the
+// raw body for anything else (such as proxies in the way)
+fn error_message(body: &str) -> String {
+    ::serde_json::from_str::<EngineMessage>(body)
+        .map(|m| m.message)
+        .unwrap_or_else(|_| String::from(body.trim()))
+}
+
+// Build the error for credentials the registry at `server` rejected
+pub fn login_error(body: &str, server: &str) -> ErrorKind {
+    ErrorKind::LoginFailed(error_message(body), String::from(server))
+}
+
+// Build the error kind for a response which did not have the status
+// code the endpoint expected
+pub fn status_error(status: u16, body: &str, path: &str) -> ErrorKind {
+    let message = error_message(body);
+    // Query parameters are not part of the endpoint
+    let path = String::from(path.split('?').next().unwrap_or(path));
+
+    match status {
+        304 => ErrorKind::NotModified(status, message, path),
+        400 => ErrorKind::BadParameter(status, message, path),
+        401 => ErrorKind::Unauthorized(status, message, path),
+        404 => ErrorKind::NotFound(status, message, path),
+        409 => ErrorKind::Conflict(status, message, path),
+        500..=599 => ErrorKind::ServerError(status, message, path),
+        _ => ErrorKind::UnexpectedStatus(status, message, path),
+    }
 }
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use serde_json;
+
+use errors::*;
+
+// The engine expects filters as a JSON encoded map from filter name to
+// a list of values, where each value is OR'd together
+#[derive(Debug, Clone)]
+pub struct Filters {
+    endpoint: &'static str,
+    supported: &'static [&'static str],
+    filters: BTreeMap<String, Vec<String>>,
+}
+
+impl Filters {
+    fn new(endpoint: &'static str, supported: &'static [&'static str]) -> Filters {
+        Filters {
+            endpoint,
+            supported,
+            filters: BTreeMap::new(),
+        }
+    }
+
+    // Add a value for the given filter, failing if the endpoint these
+    // filters are for does not support it
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        if !self.supported.contains(&key) {
+            bail!("Unsupported filter for {}: {}", self.endpoint, key);
+        }
+        self.push(key, value);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        // A map of strings to string lists can always be serialized
+        serde_json::to_string(&self.filters).unwrap()
+    }
+
+    // Add every value of `other`, which is for the same endpoint
+    fn extend(&mut self, other: &Filters) {
+        for (key, values) in &other.filters {
+            for value in values {
+                self.push(key, value);
+            }
+        }
+    }
+
+    fn push(&mut self, key: &str, value: &str) {
+        self.filters
+            .entry(String::from(key))
+            .or_default()
+            .push(String::from(value));
+    }
+}
+
+impl AsRef<Filters> for Filters {
+    fn as_ref(&self) -> &Filters {
+        self
+    }
+}
+
+// Generate a typed wrapper around `Filters` for a single endpoint, with
+// a builder method for each supported filter
+macro_rules! endpoint_filters {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident($endpoint:expr) {
+            $($method:ident => $key:expr,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        pub struct $name {
+            filters: Filters,
+        }
+
+        impl $name {
+            const SUPPORTED: &'static [&'static str] = &[$($key),*];
+
+            pub fn new() -> $name {
+                $name {
+                    filters: Filters::new($endpoint, $name::SUPPORTED),
+                }
+            }
+
+            $(
+                pub fn $method<T: ToString>(mut self, value: T) -> $name {
+                    self.filters.push($key, &value.to_string());
+                    self
+                }
+            )*
+
+            // Add every filter of `other` to these
+            pub fn merge(mut self, other: &$name) -> $name {
+                self.filters.extend(&other.filters);
+                self
+            }
+
+            // Add a filter by its engine name, for values which don't have
+            // a builder method
+            pub fn custom(mut self, key: &str, value: &str) -> Result<$name> {
+                self.filters.add(key, value)?;
+                Ok(self)
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> $name {
+                $name::new()
+            }
+        }
+
+        impl AsRef<Filters> for $name {
+            fn as_ref(&self) -> &Filters {
+                &self.filters
+            }
+        }
+    };
+}
+
+endpoint_filters! {
+    // Filters for listing containers with `GET /containers/json`
+    pub struct ContainerFilters("container list") {
+        ancestor => "ancestor",
+        before => "before",
+        exited => "exited",
+        expose => "expose",
+        health => "health",
+        id => "id",
+        isolation => "isolation",
+        is_task => "is-task",
+        label => "label",
+        name => "name",
+        network => "network",
+        publish => "publish",
+        since => "since",
+        status => "status",
+        volume => "volume",
+    }
+}
+
+endpoint_filters! {
+    // Filters for listing images with `GET /images/json`
+    pub struct ImageFilters("image list") {
+        before => "before",
+        dangling => "dangling",
+        label => "label",
+        reference => "reference",
+        since => "since",
+    }
+}
+
+endpoint_filters! {
+    // Filters for searching Docker Hub with `GET /images/search`
+    pub struct SearchFilters("image search") {
+        is_automated => "is-automated",
+        is_official => "is-official",
+        stars => "stars",
+    }
+}
+
+endpoint_filters! {
+    // Filters for removing build cache with `POST /build/prune`
+    pub struct BuildCachePruneFilters("build cache prune") {
+        until => "until",
+        id => "id",
+        parent => "parent",
+        cache_type => "type",
+        description => "description",
+        inuse => "inuse",
+        shared => "shared",
+        private => "private",
+    }
+}
+
+endpoint_filters! {
+    // Filters for removing stopped containers with `POST /containers/prune`
+    pub struct ContainerPruneFilters("container prune") {
+        until => "until",
+        label => "label",
+        label_not => "label!",
+    }
+}
@@ -1,30 +1,368 @@
-use std::io::Read;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
 
-use types::Client;
+use types::{Client, Timeouts};
 
 use errors::*;
-use utils::http::{Request, Response};
+use utils::http;
+use utils::http::{decode_chunked, Request, Response};
 
-pub trait HttpStream: Sized {
-    fn connect(client: Client) -> Result<Self>;
+// An open connection to the engine, over which requests can be made
+pub trait HttpStream {
     fn request(&mut self, req: Request) -> Result<Response>;
+
+    // Make a request whose body is read from `body`, passing the body of a
+    // successful response to `sink` as it arrives instead of returning it.
+    // Connections which cannot stream fail.
+    fn stream(
+        &mut self,
+        req: Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<Response> {
+        let _ = (body, sink);
+        bail!("{} {} needs a connection which can stream", req.method, req.path)
+    }
+}
+
+// Opens connections to the engine on behalf of a client. The unix, TCP and
+// TLS backends are connectors too, so a custom connector can add a new
+// transport or wrap a built in one, and be used with `Client::new_custom`.
+pub trait Connector: Send + Sync {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>>;
+}
+
+// A connection whose blocking reads and writes can be given timeouts
+pub trait SetTimeouts {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl SetTimeouts for TcpStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
 }
 
+impl SetTimeouts for UnixStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+// Applies a client's timeouts to a connection. It is started when the
+// connection is opened, since each request has its own connection, so the
+// deadline covers connecting as well as the request itself.
+#[derive(Clone, Debug)]
+pub struct Timer {
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+}
+
+impl Timer {
+    pub fn start(timeouts: &Timeouts) -> Timer {
+        Timer {
+            timeouts: timeouts.clone(),
+            deadline: timeouts.deadline.map(|d| Instant::now() + d),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    // How long connecting may take, allowing for the deadline
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.limit(self.timeouts.connect)
+    }
+
+    // The shorter of `timeout` and the time left before the deadline. Socket
+    // timeouts cannot be zero, so an expired deadline gives the shortest
+    // timeout possible and the next operation times out straight away.
+    fn limit(&self, timeout: Option<Duration>) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|d| d.saturating_duration_since(Instant::now()));
+        let limit = match (timeout, remaining) {
+            (Some(t), Some(r)) => Some(t.min(r)),
+            (t, r) => t.or(r),
+        };
+        limit.map(|l| l.max(Duration::from_millis(1)))
+    }
+
+    // The error for an operation which failed with `err`, which is `timeout`
+    // if it ran out of time (or the deadline error if that is what ran out)
+    pub fn error(&self, err: io::Error, timeout: ErrorKind, path: &str) -> Error {
+        if !is_timeout(&err) {
+            return Error::with_chain(err, "Could not communicate with engine");
+        }
+        if self.expired() {
+            Error::with_chain(err, ErrorKind::DeadlineExceeded(String::from(path)))
+        } else {
+            Error::with_chain(err, timeout)
+        }
+    }
+}
+
+// Socket timeouts show up as WouldBlock on unix
+pub fn is_timeout(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+// Send a request over a connection and read the response, within the
+// timer's timeouts
+pub fn exchange<S>(stream: &mut S, timer: &Timer, req: Request) -> Result<Response>
+where
+    S: Read + Write + SetTimeouts,
+{
+    // Query parameters are not part of the endpoint
+    let path = String::from(req.path.split('?').next().unwrap_or(""));
+    let req_str = http::gen_request_string(req);
+
+    stream
+        .set_write_timeout(timer.limit(timer.timeouts.write))
+        .and_then(|_| stream.write_all(req_str.as_bytes()))
+        .and_then(|_| stream.flush())
+        .map_err(|e| timer.error(e, ErrorKind::WriteTimeout(path.clone()), &path))?;
+
+    let data = read_response(
+        stream,
+        |s| {
+            s.set_read_timeout(timer.limit(timer.timeouts.read))
+                .chain_err(|| "Could not set read timeout")
+        },
+        |e| timer.error(e, ErrorKind::ReadTimeout(path.clone()), &path),
+    )?;
+
+    http::parse_response(&data).chain_err(|| "Could not parse engine HTTP response")
+}
+
+// As `exchange`, but streaming the request body from `body` and a
+// successful response's body into `sink`. Any other response's body is
+// returned as usual, so that the engine's error message can be reported.
+pub fn exchange_streaming<S>(
+    stream: &mut S,
+    timer: &Timer,
+    mut req: Request,
+    body: Option<&mut dyn Read>,
+    sink: &mut dyn Write,
+) -> Result<Response>
+where
+    S: Read + Write + SetTimeouts,
+{
+    let path = String::from(req.path.split('?').next().unwrap_or(""));
+    let write_error = |e| timer.error(e, ErrorKind::WriteTimeout(path.clone()), &path);
+
+    // The length of a streamed body is not known up front
+    if body.is_some() {
+        req.headers.remove("Content-Length");
+        req.headers
+            .insert(String::from("Transfer-Encoding"), String::from("chunked"));
+    }
+    stream
+        .set_write_timeout(timer.limit(timer.timeouts.write))
+        .and_then(|_| stream.write_all(http::gen_request_string(req).as_bytes()))
+        .map_err(write_error)?;
+
+    if let Some(body) = body {
+        let mut buffer = vec![0; 32 * 1024];
+        loop {
+            let len = body
+                .read(&mut buffer)
+                .chain_err(|| "Could not read request body")?;
+            stream
+                .write_all(format!("{:x}\r\n", len).as_bytes())
+                .and_then(|_| stream.write_all(&buffer[..len]))
+                .and_then(|_| stream.write_all(b"\r\n"))
+                .map_err(write_error)?;
+            if len == 0 {
+                break;
+            }
+        }
+    }
+    stream.flush().map_err(write_error)?;
+
+    let mut reader = TimedReader {
+        stream,
+        timer,
+        path: &path,
+    };
+
+    // Read up to the end of the headers, keeping whatever body followed
+    let mut raw = Vec::new();
+    let header_end = loop {
+        if let Some(idx) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            break idx;
+        }
+        if !reader.read_more(&mut raw)? {
+            bail!("Engine closed the connection before responding");
+        }
+    };
+    let mut response = http::parse_response_head(&String::from_utf8_lossy(&raw[..header_end]))
+        .chain_err(|| "Could not parse engine HTTP response")?;
+    let mut pending = raw.split_off(header_end + 4);
+
+    let mut error_body = Vec::new();
+    let out: &mut dyn Write = if response.status_code >= 200 && response.status_code < 300 {
+        sink
+    } else {
+        &mut error_body
+    };
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim().to_lowercase())
+    };
+
+    if response.status_code == 204 || response.status_code == 304 {
+        // These never have a body
+    } else if header("Transfer-Encoding").is_some_and(|te| te == "chunked") {
+        reader.copy_chunked(&mut pending, out)?;
+    } else if let Some(length) = header("Content-Length") {
+        let length = length
+            .parse::<usize>()
+            .chain_err(|| "Could not parse Content-Length of HTTP response")?;
+        reader.copy_exact(pending, length, out)?;
+    } else {
+        // Without a length the body runs until the engine closes the
+        // connection
+        write_body(out, &pending)?;
+        let mut buffer = Vec::new();
+        while reader.read_more(&mut buffer)? {
+            write_body(out, &buffer)?;
+            buffer.clear();
+        }
+    }
+
+    response.body = String::from_utf8_lossy(&error_body).into_owned();
+    Ok(response)
+}
+
+fn write_body(out: &mut dyn Write, data: &[u8]) -> Result<()> {
+    out.write_all(data)
+        .chain_err(|| "Could not write engine response")
+}
+
+// Reads from a connection within the timer's read timeout
+struct TimedReader<'a, S: 'a> {
+    stream: &'a mut S,
+    timer: &'a Timer,
+    path: &'a str,
+}
+
+impl<'a, S: Read + SetTimeouts> TimedReader<'a, S> {
+    // Append what the engine sends next to `buffer`, or return false if it
+    // closed the connection
+    fn read_more(&mut self, buffer: &mut Vec<u8>) -> Result<bool> {
+        let mut chunk = [0; 32 * 1024];
+        let path = String::from(self.path);
+        let len = self
+            .stream
+            .set_read_timeout(self.timer.limit(self.timer.timeouts.read))
+            .and_then(|_| self.stream.read(&mut chunk))
+            .map_err(|e| self.timer.error(e, ErrorKind::ReadTimeout(path.clone()), &path))?;
+        buffer.extend_from_slice(&chunk[..len]);
+        Ok(len > 0)
+    }
+
+    fn copy_exact(&mut self, mut pending: Vec<u8>, length: usize, out: &mut dyn Write) -> Result<()> {
+        let mut remaining = length;
+        loop {
+            let len = remaining.min(pending.len());
+            write_body(out, &pending[..len])?;
+            remaining -= len;
+            pending.clear();
+            if remaining == 0 {
+                return Ok(());
+            }
+            if !self.read_more(&mut pending)? {
+                bail!("Engine closed the connection before the response was complete");
+            }
+        }
+    }
+
+    // Decode a chunked body as it arrives, writing each chunk out in turn
+    fn copy_chunked(&mut self, pending: &mut Vec<u8>, out: &mut dyn Write) -> Result<()> {
+        loop {
+            let line_end = match pending.windows(2).position(|w| w == b"\r\n") {
+                Some(idx) => idx,
+                None => {
+                    self.read_chunk_data(pending)?;
+                    continue;
+                }
+            };
+            let size_line = String::from_utf8_lossy(&pending[..line_end]).into_owned();
+            // Chunk extensions follow a `;`
+            let size_hex = size_line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size_hex, 16)
+                .chain_err(|| format!("Invalid chunk size in HTTP response: {}", size_hex))?;
+            if size == 0 {
+                // Trailers are not used by the engine, and the connection
+                // is not reused, so there is no need to read them
+                return Ok(());
+            }
+
+            let chunk_end = line_end + 2 + size;
+            while pending.len() < chunk_end + 2 {
+                self.read_chunk_data(pending)?;
+            }
+            write_body(out, &pending[line_end + 2..chunk_end])?;
+            pending.drain(..chunk_end + 2);
+        }
+    }
+
+    fn read_chunk_data(&mut self, pending: &mut Vec<u8>) -> Result<()> {
+        if !self.read_more(pending)? {
+            bail!("Engine closed the connection before the response was complete");
+        }
+        Ok(())
+    }
+}
+
+// Read a single HTTP response from the stream. The engine keeps connections
+// open and may write a response over several packets, so keep reading until
+// the headers say the body is complete, or the connection is closed.
 pub fn read_from_stream<T: Read>(stream: &mut T) -> Result<String> {
+    read_response(stream, |_| Ok(()), |e| {
+        Error::with_chain(e, "Could not read from engine stream")
+    })
+}
+
+// Read a response, calling `before_read` ahead of each read from the stream
+// and `read_error` to explain any failure
+fn read_response<T, B, E>(stream: &mut T, mut before_read: B, read_error: E) -> Result<String>
+where
+    T: Read,
+    B: FnMut(&mut T) -> Result<()>,
+    E: Fn(io::Error) -> Error,
+{
     const BUFFER_SIZE: usize = 4096;
     let mut buffer: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
     let mut raw: Vec<u8> = Vec::new();
 
     loop {
-        let len = stream
-            .read(&mut buffer)
-            .chain_err(|| "Could not read from engine stream")?;
+        before_read(stream)?;
+        let len = stream.read(&mut buffer).map_err(&read_error)?;
 
-        for b in buffer.iter().take(len) {
-            raw.push(*b);
+        if len == 0 {
+            break;
         }
+        raw.extend_from_slice(&buffer[..len]);
 
-        if len < BUFFER_SIZE {
+        if response_complete(&raw)? {
             break;
         }
     }
@@ -33,3 +371,36 @@ pub fn read_from_stream<T: Read>(stream: &mut T) -> Result<String> {
         String::from_utf8(raw).chain_err(|| "Could not convert response to utf8 string")?;
     Ok(str_response)
 }
+
+fn response_complete(raw: &[u8]) -> Result<bool> {
+    let header_end = match raw.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(idx) => idx,
+        None => return Ok(false),
+    };
+    let head = String::from_utf8_lossy(&raw[..header_end]).to_lowercase();
+    let body = &raw[header_end + 4..];
+
+    let status_line = head.lines().next().unwrap_or("");
+    if status_line.contains(" 204 ") || status_line.contains(" 304 ") {
+        return Ok(true);
+    }
+
+    for line in head.lines().skip(1) {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+
+        if name == "content-length" {
+            let length = value
+                .parse::<usize>()
+                .chain_err(|| "Could not parse Content-Length of HTTP response")?;
+            return Ok(body.len() >= length);
+        }
+        if name == "transfer-encoding" && value == "chunked" {
+            return Ok(decode_chunked(body)?.is_some());
+        }
+    }
+
+    // Without a length the body runs until the engine closes the connection
+    Ok(false)
+}
@@ -1,10 +1,17 @@
 use std::collections::HashMap;
+use std::io::{Read, Write};
 
 use serde_json;
 
+use auth;
+use auth::RegistryAuth;
 use errors::*;
-use network::get;
+use network::{expect_status, gen_request, get, stream_request};
+use platform::Platform;
+use progress::{ProgressMessage, ProgressSink};
+use reference::{AsReference, Reference};
 use types::Client;
+use filters::{ImageFilters, SearchFilters};
 use queryparameters::{generate_path, QueryParameters};
 
 #[derive(Deserialize, Debug)]
@@ -22,18 +29,490 @@ pub struct Image {
     pub containers: i64,
 }
 
+impl Image {
+    // The image's tags as references, leaving out the `<none>:<none>` the
+    // engine reports for untagged images
+    pub fn references(&self) -> Vec<Reference> {
+        self.repo_tags
+            .iter()
+            .flatten()
+            .filter_map(|tag| Reference::parse(tag).ok())
+            .collect()
+    }
+}
+
+// Typed options for listing images, rendered to the query parameters
+// that `get_images` accepts
+#[derive(Debug, Clone, Default)]
+pub struct ListImagesOptions {
+    all: bool,
+    digests: bool,
+    shared_size: bool,
+    filters: ImageFilters,
+}
+
+impl ListImagesOptions {
+    pub fn new() -> ListImagesOptions {
+        ListImagesOptions::default()
+    }
+
+    // Include intermediate images, rather than only top level ones
+    pub fn all(mut self, all: bool) -> ListImagesOptions {
+        self.all = all;
+        self
+    }
+
+    // Populate the `repo_digests` field
+    pub fn digests(mut self, digests: bool) -> ListImagesOptions {
+        self.digests = digests;
+        self
+    }
+
+    // Compute the `shared_size` field, which the engine otherwise
+    // reports as -1
+    pub fn shared_size(mut self, shared_size: bool) -> ListImagesOptions {
+        self.shared_size = shared_size;
+        self
+    }
+
+    // Only return images created after the given image
+    pub fn since(mut self, image: &str) -> ListImagesOptions {
+        self.filters = self.filters.since(image);
+        self
+    }
+
+    // Only return images created before the given image
+    pub fn before(mut self, image: &str) -> ListImagesOptions {
+        self.filters = self.filters.before(image);
+        self
+    }
+
+    // Add filters to those set so far, including by `since` and `before`
+    pub fn filters(mut self, filters: ImageFilters) -> ListImagesOptions {
+        self.filters = self.filters.merge(&filters);
+        self
+    }
+
+    pub fn to_query_parameters(&self) -> QueryParameters {
+        let mut params = QueryParameters::new();
+        if self.all {
+            params.add("all", true);
+        }
+        if self.digests {
+            params.add("digests", true);
+        }
+        if self.shared_size {
+            params.add("shared-size", true);
+        }
+        params.add_filters(&self.filters);
+        params
+    }
+}
+
+// The details of a single image, as returned by `inspect`
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageInspect {
+    pub id: String,
+    #[serde(default)]
+    pub repo_tags: Vec<String>,
+    #[serde(default)]
+    pub repo_digests: Vec<String>,
+    pub parent: String,
+    pub comment: String,
+    pub created: String,
+    pub author: String,
+    pub architecture: String,
+    #[serde(default)]
+    pub variant: Option<String>,
+    pub os: String,
+    pub size: i64,
+}
+
+impl ImageInspect {
+    pub fn platform(&self) -> Platform {
+        Platform::new(&self.os, &self.architecture, self.variant.as_deref())
+    }
+}
+
+// Typed options for creating an image from a filesystem tarball with
+// `import`
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    image: Option<Reference>,
+    message: Option<String>,
+    changes: Vec<String>,
+    platform: Option<Platform>,
+}
+
+impl ImportOptions {
+    pub fn new() -> ImportOptions {
+        ImportOptions::default()
+    }
+
+    // The repository and tag to give the imported image
+    pub fn image(mut self, image: Reference) -> ImportOptions {
+        self.image = Some(image);
+        self
+    }
+
+    // The commit message recorded in the image's history
+    pub fn message(mut self, message: &str) -> ImportOptions {
+        self.message = Some(String::from(message));
+        self
+    }
+
+    // Apply a Dockerfile instruction, such as `CMD ["/bin/sh"]`, to the image
+    pub fn change(mut self, change: &str) -> ImportOptions {
+        self.changes.push(String::from(change));
+        self
+    }
+
+    // The platform the filesystem is for
+    pub fn platform(mut self, platform: Platform) -> ImportOptions {
+        self.platform = Some(platform);
+        self
+    }
+
+    pub fn to_query_parameters(&self) -> QueryParameters {
+        let mut params = QueryParameters::new();
+        // The image is read from the request body
+        params.add("fromSrc", "-");
+        if let Some(ref image) = self.image {
+            params.add_repository(image);
+        }
+        if let Some(ref message) = self.message {
+            params.add("message", message);
+        }
+        for change in &self.changes {
+            params.add("changes", change);
+        }
+        if let Some(ref platform) = self.platform {
+            params.add("platform", platform);
+        }
+        params
+    }
+}
+
+// A message from loading images, as returned by `Images::load`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadMessage {
+    Progress(ProgressMessage),
+    // The name of an image which was loaded, or its ID if it had no tags
+    Loaded(String),
+}
+
+impl LoadMessage {
+    fn from_progress(message: ProgressMessage) -> LoadMessage {
+        let loaded = message.stream.as_ref().and_then(|s| {
+            s.trim()
+                .strip_prefix("Loaded image: ")
+                .or_else(|| s.trim().strip_prefix("Loaded image ID: "))
+                .map(String::from)
+        });
+
+        match loaded {
+            Some(image) => LoadMessage::Loaded(image),
+            None => LoadMessage::Progress(message),
+        }
+    }
+}
+
+// An image on Docker Hub, as returned by `search`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchResult {
+    pub name: String,
+    pub description: String,
+    pub star_count: u64,
+    pub is_official: bool,
+    pub is_automated: bool,
+}
+
+// The aux message which ends a successful push
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PushAux {
+    digest: String,
+}
+
+// The digest of the manifest a push uploaded, from the messages it returned
+pub fn pushed_digest(messages: &[ProgressMessage]) -> Option<String> {
+    messages
+        .iter()
+        .rev()
+        .filter_map(|m| m.aux.clone())
+        .filter_map(|aux| serde_json::from_value::<PushAux>(aux).ok())
+        .map(|aux| aux.digest)
+        .next()
+}
+
 pub fn get_images_parse(json: &str) -> Result<Vec<Image>> {
     serde_json::from_str(json).chain_err(|| "Failed to deserialize get_containers response")
 }
 
-pub fn get_images(client: Client, args: Option<&mut QueryParameters>) -> Result<Vec<Image>> {
+pub fn get_images(client: &Client, args: Option<&mut QueryParameters>) -> Result<Vec<Image>> {
     let path = generate_path("/images/json", args);
 
-    let response = get(client, &path).chain_err(|| "Failed to get images list")?;
+    let response = get(client, &path)?;
+    expect_status(&response, &path, 200)?;
 
-    if response.status_code != 200 {
-        bail!("non-200 response from server");
+    get_images_parse(&response.body)
+}
+
+// Image endpoints, as returned by `Client::images`
+pub struct Images<'a> {
+    client: &'a Client,
+}
+
+impl<'a> Images<'a> {
+    pub fn new(client: &'a Client) -> Images<'a> {
+        Images { client }
     }
 
-    get_images_parse(&response.body)
+    pub fn list(&self, options: &ListImagesOptions) -> Result<Vec<Image>> {
+        get_images(self.client, Some(&mut options.to_query_parameters()))
+    }
+
+    // Search Docker Hub for images matching `term`, returning at most
+    // `limit` results when given
+    pub fn search(
+        &self,
+        term: &str,
+        limit: Option<u64>,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>> {
+        let mut params = QueryParameters::new();
+        params.add("term", term);
+        if let Some(limit) = limit {
+            params.add("limit", limit);
+        }
+        params.add_filters(filters);
+        let path = generate_path("/images/search", Some(&mut params));
+
+        let response = get(self.client, &path)?;
+        expect_status(&response, &path, 200)?;
+
+        serde_json::from_str(&response.body).chain_err(|| "Failed to deserialize search response")
+    }
+
+    // Write the given images, with their tags and layers, to `writer` as a
+    // tar archive which `load` accepts
+    pub fn export<R: AsReference, W: Write>(&self, images: &[R], mut writer: W) -> Result<()> {
+        let mut params = QueryParameters::new();
+        for image in images {
+            params.add("names", image.to_reference()?.familiar());
+        }
+        let path = generate_path("/images/get", Some(&mut params));
+
+        let req = gen_request("GET", &path, None);
+        let response = stream_request(self.client, req, None, &mut writer)?;
+        expect_status(&response, &path, 200)
+    }
+
+    // Load images from a tar archive, as written by `export` or `docker
+    // save`. When `quiet` is set the engine only reports the loaded images.
+    pub fn load<R: Read>(&self, reader: R, quiet: bool) -> Result<Vec<LoadMessage>> {
+        let mut messages = Vec::new();
+        self.load_with_progress(reader, quiet, |m| messages.push(m))?;
+        Ok(messages)
+    }
+
+    // As `load`, but passing each message to `on_progress` as it arrives
+    pub fn load_with_progress<R, F>(
+        &self,
+        mut reader: R,
+        quiet: bool,
+        mut on_progress: F,
+    ) -> Result<()>
+    where
+        R: Read,
+        F: FnMut(LoadMessage),
+    {
+        let mut params = QueryParameters::new();
+        params.add("quiet", quiet);
+        let path = generate_path("/images/load", Some(&mut params));
+
+        let mut req = gen_request("POST", &path, None);
+        req.headers
+            .insert(String::from("Content-Type"), String::from("application/x-tar"));
+
+        let mut progress = ProgressSink::new(&path, |m| on_progress(LoadMessage::from_progress(m)));
+        let response = stream_request(self.client, req, Some(&mut reader), &mut progress)?;
+        expect_status(&response, &path, 200)?;
+        progress.finish()
+    }
+
+    pub fn inspect<R: AsReference + ?Sized>(&self, image: &R) -> Result<ImageInspect> {
+        let path = format!("/images/{}/json", image.to_reference()?.familiar());
+
+        let response = get(self.client, &path)?;
+        expect_status(&response, &path, 200)?;
+
+        serde_json::from_str(&response.body).chain_err(|| "Failed to deserialize inspect response")
+    }
+
+    // Pull an image from its registry, returning the engine's progress
+    // messages. Images without a tag or digest are pulled as `latest`, and
+    // without a platform the engine picks the one it runs on.
+    pub fn pull<R: AsReference + ?Sized>(
+        &self,
+        image: &R,
+        platform: Option<&Platform>,
+        auth: Option<&RegistryAuth>,
+    ) -> Result<Vec<ProgressMessage>> {
+        let mut messages = Vec::new();
+        self.pull_with_progress(image, platform, auth, |m| messages.push(m))?;
+        Ok(messages)
+    }
+
+    // As `pull`, but passing each progress message to `on_progress` as it
+    // arrives
+    pub fn pull_with_progress<R, F>(
+        &self,
+        image: &R,
+        platform: Option<&Platform>,
+        auth: Option<&RegistryAuth>,
+        on_progress: F,
+    ) -> Result<()>
+    where
+        R: AsReference + ?Sized,
+        F: FnMut(ProgressMessage),
+    {
+        let image = image.to_reference()?.with_default_tag();
+        let mut params = QueryParameters::new();
+        params.add("fromImage", image.familiar_name());
+        // The engine takes a digest in place of the tag
+        if let Some(tag) = image.digest().or_else(|| image.tag()) {
+            params.add("tag", tag);
+        }
+        if let Some(platform) = platform {
+            params.add("platform", platform);
+        }
+        let path = generate_path("/images/create", Some(&mut params));
+
+        let mut req = gen_request("POST", &path, Some(String::new()));
+        auth::set_header(&mut req, auth);
+
+        let mut progress = ProgressSink::new(&path, on_progress);
+        let response = stream_request(self.client, req, None, &mut progress)?;
+        expect_status(&response, &path, 200)?;
+        progress.finish()
+    }
+
+    // Create an image from a tarball of a root filesystem, such as one
+    // exported from a container, returning the new image's ID
+    pub fn import<R: Read>(&self, mut reader: R, options: &ImportOptions) -> Result<String> {
+        let path = generate_path("/images/create", Some(&mut options.to_query_parameters()));
+
+        let mut req = gen_request("POST", &path, None);
+        req.headers
+            .insert(String::from("Content-Type"), String::from("application/x-tar"));
+
+        // The engine reports the ID as the status of its last message
+        let mut id = None;
+        let mut progress = ProgressSink::new(&path, |m: ProgressMessage| {
+            if m.status.is_some() {
+                id = m.status;
+            }
+        });
+        let response = stream_request(self.client, req, Some(&mut reader), &mut progress)?;
+        expect_status(&response, &path, 200)?;
+        progress.finish()?;
+
+        id.chain_err(|| "Engine did not report the ID of the imported image")
+    }
+
+    // Push an image to its registry, returning the engine's progress
+    // messages. Without a tag, every tag of the image is pushed.
+    pub fn push<R: AsReference + ?Sized>(
+        &self,
+        image: &R,
+        auth: Option<&RegistryAuth>,
+    ) -> Result<Vec<ProgressMessage>> {
+        let mut messages = Vec::new();
+        self.push_with_progress(image, auth, |m| messages.push(m))?;
+        Ok(messages)
+    }
+
+    // As `push`, but passing each progress message to `on_progress` as it
+    // arrives
+    pub fn push_with_progress<R, F>(
+        &self,
+        image: &R,
+        auth: Option<&RegistryAuth>,
+        on_progress: F,
+    ) -> Result<()>
+    where
+        R: AsReference + ?Sized,
+        F: FnMut(ProgressMessage),
+    {
+        let image = image.to_reference()?;
+        if image.digest().is_some() {
+            bail!("Cannot push {} by digest", image);
+        }
+
+        let base = format!("/images/{}/push", image.familiar_name());
+        let path = match image.tag() {
+            Some(tag) => {
+                let mut params = QueryParameters::new();
+                params.add("tag", tag);
+                generate_path(&base, Some(&mut params))
+            }
+            None => generate_path(&base, None),
+        };
+
+        let mut req = gen_request("POST", &path, Some(String::new()));
+        auth::set_header(&mut req, auth);
+
+        let mut progress = ProgressSink::new(&path, on_progress);
+        let response = stream_request(self.client, req, None, &mut progress)?;
+        expect_status(&response, &path, 200)?;
+        progress.finish()
+    }
+}
+
+pub fn list(client: &Client, options: &ListImagesOptions) -> Result<Vec<Image>> {
+    client.images().list(options)
+}
+
+pub fn search(
+    client: &Client,
+    term: &str,
+    limit: Option<u64>,
+    filters: &SearchFilters,
+) -> Result<Vec<SearchResult>> {
+    client.images().search(term, limit, filters)
+}
+
+pub fn export<R: AsReference, W: Write>(client: &Client, images: &[R], writer: W) -> Result<()> {
+    client.images().export(images, writer)
+}
+
+pub fn load<R: Read>(client: &Client, reader: R, quiet: bool) -> Result<Vec<LoadMessage>> {
+    client.images().load(reader, quiet)
+}
+
+pub fn inspect<R: AsReference + ?Sized>(client: &Client, image: &R) -> Result<ImageInspect> {
+    client.images().inspect(image)
+}
+
+pub fn pull<R: AsReference + ?Sized>(
+    client: &Client,
+    image: &R,
+    platform: Option<&Platform>,
+    auth: Option<&RegistryAuth>,
+) -> Result<Vec<ProgressMessage>> {
+    client.images().pull(image, platform, auth)
+}
+
+pub fn import<R: Read>(client: &Client, reader: R, options: &ImportOptions) -> Result<String> {
+    client.images().import(reader, options)
+}
+
+pub fn push<R: AsReference + ?Sized>(
+    client: &Client,
+    image: &R,
+    auth: Option<&RegistryAuth>,
+) -> Result<Vec<ProgressMessage>> {
+    client.images().push(image, auth)
 }
@@ -2,10 +2,16 @@
 
 #[macro_use]
 extern crate error_chain;
+extern crate base64;
 #[macro_use]
 extern crate lazy_static;
+#[cfg(feature = "openssl")]
 extern crate openssl;
 extern crate regex;
+#[cfg(feature = "rustls-tls")]
+extern crate rustls;
+#[cfg(feature = "rustls-tls")]
+extern crate rustls_native_certs;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
@@ -14,19 +20,41 @@ extern crate url;
 pub mod httpstream;
 pub mod tcp;
 pub mod unix;
+#[cfg(feature = "openssl")]
 pub mod tls;
+#[cfg(feature = "rustls-tls")]
+pub mod rustls_tls;
+pub mod ssh;
+pub mod proxy;
+pub mod cassette;
+pub mod retry;
 
 pub mod errors;
 pub mod types;
 pub mod network;
 pub mod utils;
 pub mod queryparameters;
+pub mod filters;
+pub mod progress;
+pub mod auth;
+pub mod credentials;
+pub mod reference;
+pub mod platform;
+pub mod context;
 
 pub mod engine;
 pub mod images;
 pub mod containers;
+pub mod distribution;
+pub mod build;
+
+#[cfg(feature = "test-support")]
+pub mod mock;
 
 // Export main types to top level of the crate
+pub use filters::{
+    BuildCachePruneFilters, ContainerFilters, ContainerPruneFilters, ImageFilters, SearchFilters,
+};
 pub use queryparameters::QueryFilter;
 pub use queryparameters::QueryParameters;
 pub use types::Client;
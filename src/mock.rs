@@ -0,0 +1,719 @@
+// An in-process fake engine for testing code which uses narwhal without a
+// real docker daemon. Routes are programmed on a `MockEngine`, which can
+// then be served over a temporary unix socket, or a loopback TCP or TLS
+// port, and every request it receives is recorded for later assertions.
+//
+// Only available with the `test-support` feature.
+
+use std;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "openssl")]
+use openssl;
+#[cfg(feature = "openssl")]
+use openssl::ssl::{SslAcceptor, SslMethod};
+
+use errors::*;
+use types::{Client, TcpClient, TlsFiles};
+use utils::http::decode_chunked;
+
+// Canned engine responses, as used by `MockEngine::with_fixtures`
+pub mod fixtures {
+    pub const GET_CONTAINERS: &str = include_str!("../tests/fixtures/get_containers.json");
+    pub const GET_IMAGES: &str = include_str!("../tests/fixtures/get_images_real.json");
+    pub const VERSION: &str = include_str!("../tests/fixtures/version.json");
+}
+
+#[derive(Clone, Debug)]
+enum MockBody {
+    // Sent with a Content-Length header
+    Full(Vec<u8>),
+    // Sent with chunked transfer encoding, one write per chunk
+    Chunked(Vec<Vec<u8>>),
+    // Sent without a length, closing the connection afterwards
+    Stream(Vec<Vec<u8>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: MockBody,
+    delay: Option<Duration>,
+}
+
+impl MockResponse {
+    // A response with an empty body
+    pub fn new(status: u16) -> MockResponse {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: MockBody::Full(Vec::new()),
+            delay: None,
+        }
+    }
+
+    pub fn json(status: u16, body: &str) -> MockResponse {
+        MockResponse::new(status)
+            .header("Content-Type", "application/json")
+            .body(body.as_bytes())
+    }
+
+    pub fn text(status: u16, body: &str) -> MockResponse {
+        MockResponse::new(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body.as_bytes())
+    }
+
+    // An error in the form the engine sends them
+    pub fn error(status: u16, message: &str) -> MockResponse {
+        let mut body = HashMap::new();
+        body.insert("message", message);
+        MockResponse::json(status, &::serde_json::to_string(&body).unwrap())
+    }
+
+    pub fn chunked(status: u16, chunks: &[&str]) -> MockResponse {
+        MockResponse {
+            body: MockBody::Chunked(chunks.iter().map(|c| c.as_bytes().to_vec()).collect()),
+            ..MockResponse::new(status).header("Content-Type", "application/json")
+        }
+    }
+
+    // A body without a length, which is complete once the connection closes
+    pub fn stream(status: u16, parts: &[&str]) -> MockResponse {
+        MockResponse {
+            body: MockBody::Stream(parts.iter().map(|p| p.as_bytes().to_vec()).collect()),
+            ..MockResponse::new(status)
+        }
+    }
+
+    pub fn body(mut self, body: &[u8]) -> MockResponse {
+        self.body = MockBody::Full(body.to_vec());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    // Wait before each write of the response, to simulate a slow engine
+    pub fn delay(mut self, delay: Duration) -> MockResponse {
+        self.delay = Some(delay);
+        self
+    }
+
+    fn write_to<W: Write>(&self, stream: &mut W) -> std::io::Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        match self.body {
+            MockBody::Full(ref body) => {
+                head.push_str(&format!("Content-Length: {}\r\n", body.len()))
+            }
+            MockBody::Chunked(_) => head.push_str("Transfer-Encoding: chunked\r\n"),
+            MockBody::Stream(_) => (),
+        }
+        head.push_str("Connection: close\r\n\r\n");
+
+        self.pause();
+        stream.write_all(head.as_bytes())?;
+        stream.flush()?;
+
+        match self.body {
+            MockBody::Full(ref body) => {
+                self.pause();
+                stream.write_all(body)?;
+            }
+            MockBody::Chunked(ref chunks) => {
+                for chunk in chunks {
+                    self.pause();
+                    stream.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+                    stream.write_all(chunk)?;
+                    stream.write_all(b"\r\n")?;
+                    stream.flush()?;
+                }
+                self.pause();
+                stream.write_all(b"0\r\n\r\n")?;
+            }
+            MockBody::Stream(ref parts) => {
+                for part in parts {
+                    self.pause();
+                    stream.write_all(part)?;
+                    stream.flush()?;
+                }
+            }
+        }
+        stream.flush()
+    }
+
+    fn pause(&self) {
+        if let Some(delay) = self.delay {
+            thread::sleep(delay);
+        }
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+// A request received by the mock engine
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Option<String>,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn body_str(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    // Header names are case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+type Handler = dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync;
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Arc<Handler>,
+}
+
+impl Route {
+    fn matches(&self, req: &RecordedRequest) -> bool {
+        if self.method != req.method {
+            return false;
+        }
+        // Routes including a query string must match it exactly
+        match (self.path.find('?'), &req.query) {
+            (Some(_), Some(query)) => self.path == format!("{}?{}", req.path, query),
+            (Some(_), None) => false,
+            (None, _) => self.path == req.path,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    routes: Mutex<Vec<Route>>,
+    requests: Mutex<Vec<RecordedRequest>>,
+}
+
+impl State {
+    fn respond(&self, req: &RecordedRequest) -> MockResponse {
+        self.requests.lock().unwrap().push(req.clone());
+
+        // Find the handler first so it is not called with the lock held
+        let handler = {
+            let routes = self.routes.lock().unwrap();
+            routes
+                .iter()
+                .rev()
+                .find(|r| r.matches(req))
+                .map(|r| r.handler.clone())
+        };
+
+        match handler {
+            Some(h) => h(req),
+            None => MockResponse::error(
+                404,
+                &format!("No mock route for {} {}", req.method, req.path),
+            ),
+        }
+    }
+}
+
+// A programmable fake engine. Cloning gives another handle to the same
+// routes and recorded requests.
+#[derive(Clone, Default)]
+pub struct MockEngine {
+    state: Arc<State>,
+}
+
+impl MockEngine {
+    pub fn new() -> MockEngine {
+        MockEngine::default()
+    }
+
+    // An engine which answers ping, version and the list endpoints with the
+    // canned fixtures
+    pub fn with_fixtures() -> MockEngine {
+        let engine = MockEngine::new();
+        engine
+            .route("GET", "/_ping", MockResponse::text(200, "OK"))
+            .route(
+                "GET",
+                "/version",
+                MockResponse::json(200, fixtures::VERSION),
+            )
+            .route(
+                "GET",
+                "/containers/json",
+                MockResponse::json(200, fixtures::GET_CONTAINERS),
+            )
+            .route(
+                "GET",
+                "/images/json",
+                MockResponse::json(200, fixtures::GET_IMAGES),
+            );
+        engine
+    }
+
+    // Respond to requests for the given method and path. The path only
+    // matches the query string if it contains one. Routes added later take
+    // precedence over earlier ones.
+    pub fn route(&self, method: &str, path: &str, response: MockResponse) -> &MockEngine {
+        self.route_fn(method, path, move |_| response.clone())
+    }
+
+    pub fn route_fn<F>(&self, method: &str, path: &str, handler: F) -> &MockEngine
+    where
+        F: Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        self.state.routes.lock().unwrap().push(Route {
+            method: String::from(method),
+            path: String::from(path),
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    // All requests received so far, in order
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path == path)
+            .collect()
+    }
+
+    // Panic unless the given endpoint was requested, returning the most
+    // recent matching request
+    pub fn assert_requested(&self, method: &str, path: &str) -> RecordedRequest {
+        match self.requests_to(method, path).pop() {
+            Some(req) => req,
+            None => panic!(
+                "Expected a request to {} {}, but received: {:?}",
+                method,
+                path,
+                self.requests()
+                    .iter()
+                    .map(|r| format!("{} {}", r.method, r.path))
+                    .collect::<Vec<_>>()
+            ),
+        }
+    }
+
+    pub fn assert_not_requested(&self, method: &str, path: &str) {
+        let count = self.requests_to(method, path).len();
+        if count != 0 {
+            panic!(
+                "Expected no requests to {} {}, but received {}",
+                method, path, count
+            );
+        }
+    }
+
+    pub fn serve_unix(&self) -> Result<MockServer> {
+        let dir = temp_dir()?;
+        let socket_path = dir.join("docker.sock");
+        let listener =
+            UnixListener::bind(&socket_path).chain_err(|| "Could not bind mock socket")?;
+        listener
+            .set_nonblocking(true)
+            .chain_err(|| "Could not configure mock socket")?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let state = self.state.clone();
+        let stop = shutdown.clone();
+        let thread = thread::spawn(move || {
+            accept_loop(
+                &stop,
+                || listener.accept().map(|(s, _)| s),
+                move |stream| {
+                    stream.set_nonblocking(false)?;
+                    serve_connection(&state, stream)
+                },
+            )
+        });
+
+        Ok(MockServer {
+            listener: Listener::Unix(socket_path),
+            dir: Some(dir),
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn serve_tcp(&self) -> Result<MockServer> {
+        let (listener, port) = tcp_listener()?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let state = self.state.clone();
+        let stop = shutdown.clone();
+        let thread = thread::spawn(move || {
+            accept_loop(
+                &stop,
+                || listener.accept().map(|(s, _)| s),
+                move |stream| {
+                    stream.set_nonblocking(false)?;
+                    let result = serve_connection(&state, stream.try_clone()?);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    result
+                },
+            )
+        });
+
+        Ok(MockServer {
+            listener: Listener::Tcp(port),
+            dir: None,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    // Serve over TLS with a freshly generated self-signed certificate. The
+    // client returned by the server trusts this certificate and uses it as
+    // its own client certificate. The server is implemented with OpenSSL, so
+    // needs the `openssl` feature.
+    #[cfg(feature = "openssl")]
+    pub fn serve_tls(&self) -> Result<MockServer> {
+        let dir = temp_dir()?;
+        let (key, cert) = self_signed_certificate().chain_err(|| "Could not generate mock cert")?;
+        write_pem(&dir.join("key.pem"), &key)?;
+        write_pem(&dir.join("cert.pem"), &cert)?;
+        write_pem(&dir.join("ca.pem"), &cert)?;
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())
+            .chain_err(|| "Could not create TLS acceptor")?;
+        acceptor
+            .set_private_key_file(dir.join("key.pem"), openssl::ssl::SslFiletype::PEM)
+            .chain_err(|| "Could not set mock TLS key")?;
+        acceptor
+            .set_certificate_file(dir.join("cert.pem"), openssl::ssl::SslFiletype::PEM)
+            .chain_err(|| "Could not set mock TLS certificate")?;
+        let acceptor = acceptor.build();
+
+        let (listener, port) = tcp_listener()?;
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let state = self.state.clone();
+        let stop = shutdown.clone();
+        let thread = thread::spawn(move || {
+            accept_loop(
+                &stop,
+                || listener.accept().map(|(s, _)| s),
+                move |stream| {
+                    stream.set_nonblocking(false)?;
+                    let mut tls = acceptor
+                        .accept(stream)
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
+                    let result = serve_connection(&state, &mut tls);
+                    let _ = tls.shutdown();
+                    result
+                },
+            )
+        });
+
+        Ok(MockServer {
+            listener: Listener::Tls(port),
+            dir: Some(dir),
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+}
+
+enum Listener {
+    Unix(PathBuf),
+    Tcp(u16),
+    #[cfg_attr(not(feature = "openssl"), allow(dead_code))]
+    Tls(u16),
+}
+
+// A running mock engine, which stops serving when dropped
+pub struct MockServer {
+    listener: Listener,
+    // Holds the socket or TLS material, removed when the server stops
+    dir: Option<PathBuf>,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MockServer {
+    // A client configured to talk to this server
+    pub fn client(&self) -> Client {
+        match self.listener {
+            Listener::Unix(ref path) => Client::new_unix(path.to_string_lossy().into_owned()),
+            Listener::Tcp(port) => Client::new_tcp(TcpClient {
+                host: String::from("127.0.0.1"),
+                port,
+            }),
+            Listener::Tls(port) => {
+                // TLS servers always have a directory for their certificates
+                let dir = self.dir.as_ref().unwrap();
+                Client::new_tls(
+                    TcpClient {
+                        host: String::from("127.0.0.1"),
+                        port,
+                    },
+                    TlsFiles {
+                        key: dir.join("key.pem").to_string_lossy().into_owned(),
+                        cert: dir.join("cert.pem").to_string_lossy().into_owned(),
+                        ca: dir.join("ca.pem").to_string_lossy().into_owned(),
+                    },
+                )
+            }
+        }
+    }
+
+    // The directory holding a TLS server's key.pem, cert.pem and ca.pem
+    pub fn cert_path(&self) -> Option<&Path> {
+        match self.listener {
+            Listener::Tls(_) => self.dir.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn socket_path(&self) -> Option<&Path> {
+        match self.listener {
+            Listener::Unix(ref path) => Some(path),
+            _ => None,
+        }
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        match self.listener {
+            Listener::Tcp(port) | Listener::Tls(port) => Some(port),
+            Listener::Unix(_) => None,
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(ref dir) = self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn accept_loop<S, A, H>(shutdown: &AtomicBool, accept: A, handle: H)
+where
+    S: Send + 'static,
+    A: Fn() -> std::io::Result<S>,
+    H: Fn(S) -> std::io::Result<()> + Send + Sync + 'static,
+{
+    let handle = Arc::new(handle);
+
+    while !shutdown.load(Ordering::SeqCst) {
+        match accept() {
+            Ok(stream) => {
+                // Serve each connection separately, so that a slow
+                // streaming response doesn't hold up other requests
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    let _ = handle(stream);
+                });
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(_) => break,
+        }
+    }
+}
+
+fn serve_connection<S: Read + Write>(state: &State, mut stream: S) -> std::io::Result<()> {
+    let req = match read_request(&mut stream)? {
+        Some(req) => req,
+        None => return Ok(()),
+    };
+    let response = state.respond(&req);
+    response.write_to(&mut stream)
+}
+
+fn read_request<S: Read>(stream: &mut S) -> std::io::Result<Option<RecordedRequest>> {
+    let mut raw = Vec::new();
+    let mut buffer = [0; 4096];
+
+    let header_end = loop {
+        if let Some(idx) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            break idx;
+        }
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        raw.extend_from_slice(&buffer[..len]);
+    };
+
+    let head = String::from_utf8_lossy(&raw[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = String::from(request_line.next().unwrap_or(""));
+    let target = request_line.next().unwrap_or("");
+    let (path, query) = match target.find('?') {
+        Some(idx) => (
+            String::from(&target[..idx]),
+            Some(String::from(&target[idx + 1..])),
+        ),
+        None => (String::from(target), None),
+    };
+
+    let mut headers = HashMap::new();
+    for line in lines {
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        headers.insert(String::from(name), String::from(value));
+    }
+
+    let mut req = RecordedRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: raw[header_end + 4..].to_vec(),
+    };
+
+    let chunked = req.header("Transfer-Encoding") == Some("chunked");
+    let length = req
+        .header("Content-Length")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    loop {
+        if chunked {
+            let decoded = decode_chunked(&req.body)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+            if let Some((body, _)) = decoded {
+                req.body = body;
+                break;
+            }
+        } else if req.body.len() >= length {
+            break;
+        }
+
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        req.body.extend_from_slice(&buffer[..len]);
+    }
+
+    Ok(Some(req))
+}
+
+fn tcp_listener() -> Result<(TcpListener, u16)> {
+    let listener = TcpListener::bind("127.0.0.1:0").chain_err(|| "Could not bind mock port")?;
+    listener
+        .set_nonblocking(true)
+        .chain_err(|| "Could not configure mock port")?;
+    let port = listener
+        .local_addr()
+        .chain_err(|| "Could not get mock port")?
+        .port();
+
+    Ok((listener, port))
+}
+
+fn temp_dir() -> Result<PathBuf> {
+    lazy_static! {
+        static ref COUNTER: AtomicUsize = AtomicUsize::new(0);
+    }
+
+    let dir = std::env::temp_dir().join(format!(
+        "narwhal-mock-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&dir).chain_err(|| "Could not create mock engine directory")?;
+
+    Ok(dir)
+}
+
+#[cfg(feature = "openssl")]
+fn write_pem(path: &Path, pem: &[u8]) -> Result<()> {
+    let mut f = fs::File::create(path).chain_err(|| "Could not create mock PEM file")?;
+    f.write_all(pem)
+        .chain_err(|| "Could not write mock PEM file")
+}
+
+#[cfg(feature = "openssl")]
+fn self_signed_certificate() -> ::std::result::Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack>
+{
+    use openssl::asn1::Asn1Time;
+    use openssl::bn::BigNum;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509Builder, X509NameBuilder};
+
+    let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", "localhost")?;
+    let name = name.build();
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&*BigNum::from_u32(1)?.to_asn1_integer()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&*Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&*Asn1Time::days_from_now(1)?)?;
+    // Valid for the addresses clients connect to, so that clients which
+    // verify the engine can trust the certificate itself
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((key.private_key_to_pem_pkcs8()?, builder.build().to_pem()?))
+}
@@ -1,53 +1,183 @@
+use std::cell::Cell;
+use std::io;
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Instant;
+
 use errors::*;
-use types;
 use types::Client;
 
+use cassette::RecordingStream;
 use httpstream::HttpStream;
-use tcp::TcpStream;
-use unix::UnixStream;
-use tls::TlsStream;
+use retry::RetryAttempt;
 
 use utils::http;
 
-pub fn get(client: Client, path: &str) -> Result<http::Response> {
+pub fn get(client: &Client, path: &str) -> Result<http::Response> {
     let req = gen_request("GET", path, None);
 
     perform_client_request(client, req)
 }
 
-pub fn post(client: Client, path: &str, data: &str) -> Result<http::Response> {
+pub fn post(client: &Client, path: &str, data: &str) -> Result<http::Response> {
     let req = gen_request("POST", path, Some(String::from(data)));
 
     perform_client_request(client, req)
 }
 
-fn perform_client_request(client: Client, req: http::Request) -> Result<http::Response> {
-    match client.backend {
-        types::CommsBackend::Unix => {
-            let stream =
-                UnixStream::connect(client).chain_err(|| "Could not connect to unix socket")?;
+// Check that the engine responded with the status code the endpoint
+// documents as success, otherwise return the matching error kind
+pub fn expect_status(response: &http::Response, path: &str, expected: u16) -> Result<()> {
+    if response.status_code != expected {
+        return Err(status_error(response.status_code, &response.body, path).into());
+    }
+
+    Ok(())
+}
+
+// Make a request built with `gen_request`, for endpoints which need headers
+// that `get` and `post` do not send
+pub fn perform_client_request(client: &Client, req: http::Request) -> Result<http::Response> {
+    with_retries(client, &req, &|| true, |client| {
+        let mut stream = connect(client, &req)?;
+        request_result(stream.request(req.clone()))
+    })
+}
+
+// Make a request whose body is streamed from `body`, passing the body of a
+// successful response to `sink` as it arrives. Streams can last for as long
+// as the engine keeps sending, so are not limited by the client's deadline.
+pub fn stream_request(
+    client: &Client,
+    req: http::Request,
+    mut body: Option<&mut dyn Read>,
+    sink: &mut dyn Write,
+) -> Result<http::Response> {
+    let mut client = client.clone();
+    client.timeouts = client.timeouts.for_streaming();
 
-            perform_request(stream, req)
+    // Only GET and HEAD requests are retried, which have no body, and not
+    // once any of the response has reached the sink, so nothing is sent or
+    // received twice
+    let received = Cell::new(false);
+    with_retries(&client, &req, &|| !received.get(), |client| {
+        let mut stream = connect(client, &req)?;
+        let body = body.as_mut().map(|b| &mut **b as &mut dyn Read);
+        let mut sink = TrackedSink {
+            sink: &mut *sink,
+            received: &received,
+        };
+        request_result(stream.stream(req.clone(), body, &mut sink))
+    })
+}
+
+// Records whether anything has been passed on to a sink
+struct TrackedSink<'a> {
+    sink: &'a mut dyn Write,
+    received: &'a Cell<bool>,
+}
+
+impl<'a> Write for TrackedSink<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        if written > 0 {
+            self.received.set(true);
         }
-        types::CommsBackend::TCP => {
-            let stream =
-                TcpStream::connect(client).chain_err(|| "Could not connect to tcp address")?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
 
-            perform_request(stream, req)
+// Make a request, retrying it as the client's policy allows for as long as
+// `can_retry` says a failed attempt can be made again. The client's deadline
+// covers every attempt and the delays between them, so each attempt is made
+// with a client which only has the time that is left.
+fn with_retries<F>(
+    client: &Client,
+    req: &http::Request,
+    can_retry: &dyn Fn() -> bool,
+    mut attempt_request: F,
+) -> Result<http::Response>
+where
+    F: FnMut(&Client) -> Result<http::Response>,
+{
+    let policy = match client.retry {
+        Some(ref policy) if policy.applies_to(&req.method) => policy,
+        _ => return attempt_request(client),
+    };
+    // Query parameters are not part of the endpoint
+    let path = req.path.split('?').next().unwrap_or("");
+
+    let started = Instant::now();
+    let mut attempt_client = client.clone();
+    let mut attempt = 1;
+    loop {
+        let result = attempt_request(&attempt_client);
+        if attempt >= policy.max_attempts || !can_retry() {
+            return result;
+        }
+        let delay = policy.delay(attempt);
+        let reason = match policy.retry_reason(&result) {
+            Some(reason) => reason,
+            None => return result,
+        };
+
+        // Give up rather than wait past the deadline
+        if let Some(deadline) = client.timeouts.deadline {
+            match deadline.checked_sub(started.elapsed()) {
+                Some(remaining) if remaining > delay => {
+                    attempt_client.timeouts.deadline = Some(remaining - delay)
+                }
+                _ => {
+                    let kind = ErrorKind::DeadlineExceeded(String::from(path));
+                    return Err(match result {
+                        Err(e) => Error::with_chain(e, kind),
+                        Ok(_) => kind.into(),
+                    });
+                }
+            }
         }
-        types::CommsBackend::TLS => {
-            let stream =
-                TlsStream::connect(client).chain_err(|| "Could not connect to tls address")?;
 
-            perform_request(stream, req)
+        policy.notify(&RetryAttempt {
+            method: &req.method,
+            path,
+            attempt,
+            delay,
+            reason,
+        });
+        thread::sleep(delay);
+        attempt += 1;
+    }
+}
+
+fn connect(client: &Client, req: &http::Request) -> Result<Box<dyn HttpStream>> {
+    // Query parameters are not part of the endpoint
+    let path = String::from(req.path.split('?').next().unwrap_or(""));
+
+    // Timeouts are returned as they are, so that callers can tell them
+    // apart from the engine being unreachable
+    let stream = match client.connector().connect(client) {
+        Ok(stream) => stream,
+        Err(e) if e.is_timeout() => return Err(e),
+        Err(e) => return Err(Error::with_chain(e, ErrorKind::ConnectionFailed(path))),
+    };
+
+    if let Some(ref cassette) = client.cassette {
+        if !client.is_replay() {
+            return Ok(Box::new(RecordingStream::new(stream, cassette.clone())));
         }
     }
+    Ok(stream)
 }
 
-fn perform_request<T: HttpStream>(mut stream: T, req: http::Request) -> Result<http::Response> {
-    stream
-        .request(req)
-        .chain_err(|| "Could not perform HTTP request")
+fn request_result(result: Result<http::Response>) -> Result<http::Response> {
+    match result {
+        Err(e) if e.is_timeout() => Err(e),
+        result => result.chain_err(|| "Could not perform HTTP request"),
+    }
 }
 
 pub fn gen_request(method: &str, path: &str, body: Option<String>) -> http::Request {
@@ -59,5 +189,15 @@ pub fn gen_request(method: &str, path: &str, body: Option<String>) -> http::Requ
     };
     req.headers
         .insert(String::from("Host"), String::from("narwhal"));
+
+    // The engine will ignore a request body unless it knows its length
+    if let Some(ref b) = req.body {
+        req.headers
+            .insert(String::from("Content-Length"), b.len().to_string());
+        if !b.is_empty() {
+            req.headers
+                .insert(String::from("Content-Type"), String::from("application/json"));
+        }
+    }
     req
 }
@@ -0,0 +1,162 @@
+// The platform an image is built for or an engine runs on, such as
+// `linux/arm/v7`. Names are normalized the way the engine does, so that
+// `aarch64` and `arm64` are the same architecture.
+
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+
+// Deserialized platforms are normalized, as registries report 64 bit ARM
+// as `arm64/v8` where `new` gives `arm64`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "RawPlatform")]
+pub struct Platform {
+    pub os: String,
+    pub architecture: String,
+    // The architecture's version, such as `v7` for 32 bit ARM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
+    pub os_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+}
+
+// A platform as it is given in JSON, before it is normalized
+#[derive(Deserialize)]
+struct RawPlatform {
+    os: String,
+    architecture: String,
+    variant: Option<String>,
+    #[serde(rename = "os.version")]
+    os_version: Option<String>,
+    #[serde(rename = "os.features")]
+    os_features: Option<Vec<String>>,
+    features: Option<Vec<String>>,
+}
+
+impl From<RawPlatform> for Platform {
+    fn from(raw: RawPlatform) -> Platform {
+        Platform {
+            os_version: raw.os_version,
+            os_features: raw.os_features,
+            features: raw.features,
+            ..Platform::new(&raw.os, &raw.architecture, raw.variant.as_deref())
+        }
+    }
+}
+
+impl Platform {
+    pub fn new(os: &str, architecture: &str, variant: Option<&str>) -> Platform {
+        let (architecture, variant) = normalize_architecture(architecture, variant);
+        Platform {
+            os: os.to_lowercase(),
+            architecture,
+            variant,
+            os_version: None,
+            os_features: None,
+            features: None,
+        }
+    }
+
+    // Parse `os/architecture[/variant]`, or a bare architecture, which is
+    // taken to be for Linux
+    pub fn parse(platform: &str) -> Result<Platform> {
+        let parts: Vec<&str> = platform.split('/').collect();
+        if parts.iter().any(|p| p.is_empty()) {
+            bail!("Invalid platform {}", platform);
+        }
+
+        match parts[..] {
+            [architecture] => Ok(Platform::new("linux", architecture, None)),
+            [os, architecture] => Ok(Platform::new(os, architecture, None)),
+            [os, architecture, variant] => Ok(Platform::new(os, architecture, Some(variant))),
+            _ => bail!("Invalid platform {}", platform),
+        }
+    }
+
+    // The platform of an engine, from the `OSType` and `Architecture` it
+    // reports in its info, which are the kernel's names such as `armv7l`
+    pub fn from_engine(os_type: &str, architecture: &str) -> Platform {
+        let (architecture, variant) = match architecture {
+            "armv5l" | "armv5tel" => ("arm", Some("v5")),
+            "armv6l" => ("arm", Some("v6")),
+            "armv7l" => ("arm", Some("v7")),
+            "armv8l" => ("arm", Some("v8")),
+            architecture => (architecture, None),
+        };
+        Platform::new(os_type, architecture, variant)
+    }
+
+    // Whether an image for this platform can run on `host`, as containerd
+    // decides. ARM hosts run images for earlier variants, 64 bit hosts also
+    // run 32 bit images (ARM up to v8, and 386 on amd64), and images without
+    // a variant run on any host of their architecture.
+    pub fn runs_on(&self, host: &Platform) -> bool {
+        // Either side may have been built field by field rather than by `new`
+        let image = Platform::new(&self.os, &self.architecture, self.variant.as_deref());
+        let host = Platform::new(&host.os, &host.architecture, host.variant.as_deref());
+        if image.os != host.os {
+            return false;
+        }
+
+        match (image.architecture.as_str(), host.architecture.as_str()) {
+            // 64 bit ARM hosts are ARMv8
+            ("arm", "arm64") => image.variant.as_deref().map_or(0, arm_version) <= 8,
+            ("386", "amd64") => true,
+            (image_arch, host_arch) if image_arch != host_arch => false,
+            (architecture, _) => match (image.variant.as_deref(), host.variant.as_deref()) {
+                (None, _) => true,
+                (Some(_), None) => false,
+                (Some(image), Some(host)) if architecture == "arm" => {
+                    arm_version(image) <= arm_version(host)
+                }
+                (Some(image), Some(host)) => image == host,
+            },
+        }
+    }
+}
+
+// Map the names architectures go by to the ones the engine uses
+fn normalize_architecture(architecture: &str, variant: Option<&str>) -> (String, Option<String>) {
+    let architecture = architecture.to_lowercase();
+    let variant = variant.map(|v| v.to_lowercase());
+
+    let (architecture, variant) = match (architecture.as_str(), variant.as_deref()) {
+        ("i386", _) => ("386", None),
+        ("x86_64", _) | ("x86-64", _) => ("amd64", None),
+        ("aarch64", _) | ("arm64", Some("v8")) | ("arm64", None) => ("arm64", None),
+        ("armhf", _) => ("arm", Some("v7")),
+        ("armel", _) => ("arm", Some("v6")),
+        // 32 bit ARM without a variant is v7, as it is for the engine
+        ("arm", None) => ("arm", Some("v7")),
+        (architecture, variant) => (architecture, variant),
+    };
+    (String::from(architecture), variant.map(String::from))
+}
+
+fn arm_version(variant: &str) -> u32 {
+    variant.trim_start_matches('v').parse().unwrap_or(0)
+}
+
+// The platform as `os/architecture[/variant]`, as the engine accepts it
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(ref variant) = self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = Error;
+
+    fn from_str(platform: &str) -> Result<Platform> {
+        Platform::parse(platform)
+    }
+}
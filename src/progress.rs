@@ -0,0 +1,118 @@
+// Endpoints which take a while, such as loading or pushing images, report
+// their progress as a stream of JSON messages. If the operation fails part
+// way through, the engine has already sent a successful status, so reports
+// the failure as a message in the stream instead.
+
+use std::io;
+use std::io::Write;
+
+use serde_json;
+
+use errors::*;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ProgressDetail {
+    pub current: Option<u64>,
+    pub total: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorDetail {
+    pub code: Option<i64>,
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ProgressMessage {
+    // The layer or image the message is about
+    pub id: Option<String>,
+    pub status: Option<String>,
+    // Output to show to the user
+    pub stream: Option<String>,
+    // A progress bar for display, which `progress_detail` has the numbers for
+    pub progress: Option<String>,
+    pub progress_detail: Option<ProgressDetail>,
+    pub error: Option<String>,
+    pub error_detail: Option<ErrorDetail>,
+    // Extra information specific to the endpoint, such as the digest of a
+    // pushed image
+    pub aux: Option<serde_json::Value>,
+}
+
+impl ProgressMessage {
+    // The error this message reports, if it reports one
+    pub fn error_message(&self) -> Option<String> {
+        self.error_detail
+            .as_ref()
+            .and_then(|d| d.message.clone())
+            .or_else(|| self.error.clone())
+    }
+}
+
+// Decodes progress messages from a response body as it is written, passing
+// each to a handler. Messages are separated by newlines.
+pub struct ProgressSink<F> {
+    path: String,
+    handler: F,
+    // Part of a message which has not been completely written yet
+    buffer: Vec<u8>,
+    error: Option<Error>,
+}
+
+impl<F: FnMut(ProgressMessage)> ProgressSink<F> {
+    pub fn new(path: &str, handler: F) -> ProgressSink<F> {
+        ProgressSink {
+            // Query parameters are not part of the endpoint
+            path: String::from(path.split('?').next().unwrap_or("")),
+            handler,
+            buffer: Vec::new(),
+            error: None,
+        }
+    }
+
+    // Handle any message left without a trailing newline, and return the
+    // first error the stream reported
+    pub fn finish(mut self) -> Result<()> {
+        let rest = ::std::mem::take(&mut self.buffer);
+        self.handle(&rest);
+
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn handle(&mut self, line: &[u8]) {
+        if line.iter().all(|b| b.is_ascii_whitespace()) || self.error.is_some() {
+            return;
+        }
+
+        match serde_json::from_slice::<ProgressMessage>(line) {
+            Ok(message) => {
+                if let Some(error) = message.error_message() {
+                    self.error = Some(ErrorKind::StreamError(error, self.path.clone()).into());
+                }
+                (self.handler)(message);
+            }
+            Err(e) => {
+                self.error = Some(Error::with_chain(e, "Could not parse progress message"));
+            }
+        }
+    }
+}
+
+impl<F: FnMut(ProgressMessage)> Write for ProgressSink<F> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        while let Some(idx) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..idx + 1).collect();
+            self.handle(&line);
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -3,6 +3,9 @@ use std::fmt::Display;
 use url::form_urlencoded;
 use serde_json;
 
+use filters::Filters;
+use reference::Reference;
+
 pub struct QueryParameters {
     encoder: form_urlencoded::Serializer<String>,
 }
@@ -22,7 +25,22 @@ impl QueryParameters {
         // Don't worry about the unwrap, as the type system says
         // it has to be serializable
         let filter_str = serde_json::to_string(&filter).unwrap();
-        self.encoder.append_pair("filter", &filter_str);
+        self.encoder.append_pair("filters", &filter_str);
+    }
+    pub fn add_filters<F: AsRef<Filters>>(&mut self, filters: &F) {
+        let filters = filters.as_ref();
+        if !filters.is_empty() {
+            self.encoder.append_pair("filters", &filters.to_json());
+        }
+    }
+
+    // Name the image an endpoint creates with the `repo` and `tag`
+    // parameters, without which it is only known by its ID
+    pub fn add_repository(&mut self, image: &Reference) {
+        self.encoder.append_pair("repo", &image.familiar_name());
+        if let Some(tag) = image.tag() {
+            self.encoder.append_pair("tag", tag);
+        }
     }
 
     pub fn to_string(&mut self) -> String {
@@ -0,0 +1,238 @@
+// Image references, such as `busybox`, `balena/narwhal:v1` or
+// `registry.local:5000/narwhal@sha256:...`, parsed and normalized the way
+// the docker CLI does. Names without a registry are on Docker Hub, and
+// single component names there are in the `library` namespace.
+
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use errors::*;
+
+pub const DEFAULT_DOMAIN: &str = "docker.io";
+pub const DEFAULT_TAG: &str = "latest";
+
+// The domain Docker Hub references are normalized from
+const LEGACY_DEFAULT_DOMAIN: &str = "index.docker.io";
+const OFFICIAL_NAMESPACE: &str = "library/";
+const NAME_TOTAL_LENGTH_MAX: usize = 255;
+
+lazy_static! {
+    static ref DOMAIN_RE: Regex = Regex::new(
+        r"^(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9])(?:\.(?:[a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]))*(?::[0-9]+)?$"
+    ).unwrap();
+    static ref COMPONENT_RE: Regex =
+        Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|[-]+)[a-z0-9]+)*$").unwrap();
+    static ref TAG_RE: Regex = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$").unwrap();
+    static ref DIGEST_RE: Regex =
+        Regex::new(r"^[A-Za-z][A-Za-z0-9]*(?:[-_+.][A-Za-z][A-Za-z0-9]*)*:[0-9a-fA-F]{32,}$")
+            .unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reference {
+    domain: String,
+    path: String,
+    tag: Option<String>,
+    digest: Option<String>,
+}
+
+impl Reference {
+    pub fn parse(reference: &str) -> Result<Reference> {
+        let invalid = |reason: &str| format!("Invalid image reference {}: {}", reference, reason);
+
+        let (name, digest) = match reference.split_once('@') {
+            Some((name, digest)) => {
+                if !DIGEST_RE.is_match(digest) {
+                    bail!(invalid("invalid digest"));
+                }
+                (name, Some(String::from(digest)))
+            }
+            None => (reference, None),
+        };
+
+        // A colon after the last slash starts the tag, while one before it
+        // is part of a registry's port
+        let (name, tag) = match name.rfind(':') {
+            Some(idx) if !name[idx..].contains('/') => {
+                let tag = &name[idx + 1..];
+                if !TAG_RE.is_match(tag) {
+                    bail!(invalid("invalid tag"));
+                }
+                (&name[..idx], Some(String::from(tag)))
+            }
+            _ => (name, None),
+        };
+
+        if name.is_empty() {
+            bail!(invalid("no repository name"));
+        }
+        if name.len() > NAME_TOTAL_LENGTH_MAX {
+            bail!(invalid("repository name is too long"));
+        }
+
+        let (domain, path) = split_domain(name);
+        if !DOMAIN_RE.is_match(&domain) {
+            bail!(invalid("invalid registry"));
+        }
+        if path.to_lowercase() != path {
+            bail!(invalid("repository name must be lowercase"));
+        }
+        if !path.split('/').all(|c| COMPONENT_RE.is_match(c)) {
+            bail!(invalid("invalid repository name"));
+        }
+
+        Ok(Reference {
+            domain,
+            path,
+            tag,
+            digest,
+        })
+    }
+
+    // The registry, such as `docker.io` or `registry.local:5000`
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    // The repository within the registry, such as `library/busybox`
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
+    }
+
+    // The fully qualified repository, such as `docker.io/library/busybox`
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.domain, self.path)
+    }
+
+    // The repository as the docker CLI shows it, without the defaults
+    // filled in by normalization, such as `busybox`
+    pub fn familiar_name(&self) -> String {
+        if self.domain != DEFAULT_DOMAIN {
+            return self.name();
+        }
+        match self.path.strip_prefix(OFFICIAL_NAMESPACE) {
+            Some(path) if !path.contains('/') => String::from(path),
+            _ => self.path.clone(),
+        }
+    }
+
+    // The whole reference as the docker CLI shows it, such as
+    // `busybox:latest`
+    pub fn familiar(&self) -> String {
+        let mut familiar = self.familiar_name();
+        push_suffix(&mut familiar, self.tag(), self.digest());
+        familiar
+    }
+
+    // Tag a reference with `latest` when it has neither a tag nor a digest,
+    // as the engine does
+    pub fn with_default_tag(mut self) -> Reference {
+        if self.tag.is_none() && self.digest.is_none() {
+            self.tag = Some(String::from(DEFAULT_TAG));
+        }
+        self
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Result<Reference> {
+        if !TAG_RE.is_match(tag) {
+            bail!("Invalid image tag {}", tag);
+        }
+        self.tag = Some(String::from(tag));
+        Ok(self)
+    }
+}
+
+// Split off the registry, which the first component of a name only is when
+// it looks like a host name
+fn split_domain(name: &str) -> (String, String) {
+    let (domain, path) = match name.split_once('/') {
+        Some((first, rest))
+            if first.contains('.')
+                || first.contains(':')
+                || first == "localhost"
+                || first.to_lowercase() != first =>
+        {
+            (first, rest)
+        }
+        _ => (DEFAULT_DOMAIN, name),
+    };
+
+    let domain = match domain {
+        LEGACY_DEFAULT_DOMAIN => DEFAULT_DOMAIN,
+        domain => domain,
+    };
+    let path = if domain == DEFAULT_DOMAIN && !path.contains('/') {
+        format!("{}{}", OFFICIAL_NAMESPACE, path)
+    } else {
+        String::from(path)
+    };
+    (String::from(domain), path)
+}
+
+fn push_suffix(name: &mut String, tag: Option<&str>, digest: Option<&str>) {
+    if let Some(tag) = tag {
+        name.push(':');
+        name.push_str(tag);
+    }
+    if let Some(digest) = digest {
+        name.push('@');
+        name.push_str(digest);
+    }
+}
+
+// The fully qualified reference, such as `docker.io/library/busybox:latest`
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut name = self.name();
+        push_suffix(&mut name, self.tag(), self.digest());
+        f.write_str(&name)
+    }
+}
+
+impl FromStr for Reference {
+    type Err = Error;
+
+    fn from_str(reference: &str) -> Result<Reference> {
+        Reference::parse(reference)
+    }
+}
+
+// Anything image endpoints accept as an image name: a parsed Reference, or
+// a string which is parsed when the request is made
+pub trait AsReference {
+    fn to_reference(&self) -> Result<Reference>;
+}
+
+impl AsReference for Reference {
+    fn to_reference(&self) -> Result<Reference> {
+        Ok(self.clone())
+    }
+}
+
+impl AsReference for str {
+    fn to_reference(&self) -> Result<Reference> {
+        Reference::parse(self)
+    }
+}
+
+impl AsReference for String {
+    fn to_reference(&self) -> Result<Reference> {
+        Reference::parse(self)
+    }
+}
+
+impl<T: AsReference + ?Sized> AsReference for &T {
+    fn to_reference(&self) -> Result<Reference> {
+        (**self).to_reference()
+    }
+}
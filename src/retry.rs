@@ -0,0 +1,172 @@
+// Retrying requests which fail because the engine is briefly unavailable,
+// such as while it restarts. Only GET and HEAD requests are retried, as
+// they are safe to repeat, and only when the engine could not be reached,
+// dropped the connection or answered with a server error.
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use errors::*;
+use utils::http::Response;
+
+// Why a request is being retried
+#[derive(Debug)]
+pub enum RetryReason<'a> {
+    // Connecting to the engine failed, or it dropped the connection
+    Error(&'a Error),
+    // The engine responded with this 5xx status
+    Status(u16),
+}
+
+// Passed to the policy's hooks before each retry
+#[derive(Debug)]
+pub struct RetryAttempt<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    // The attempt which failed, counting from 1
+    pub attempt: u32,
+    // How long until the next attempt
+    pub delay: Duration,
+    pub reason: RetryReason<'a>,
+}
+
+type Hook = Arc<dyn Fn(&RetryAttempt) + Send + Sync>;
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    // How many times to try a request in total, including the first
+    pub max_attempts: u32,
+    // The delay before the first retry, which doubles after each attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // Wait a random time between half and all of the backoff, so that
+    // clients which failed together do not all retry together
+    pub jitter: bool,
+    hooks: Vec<Hook>,
+}
+
+impl RetryPolicy {
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            jitter: true,
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> RetryPolicy {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    // Call `hook` before each retry, for logging or metrics
+    pub fn on_retry<F>(mut self, hook: F) -> RetryPolicy
+    where
+        F: Fn(&RetryAttempt) + Send + Sync + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    pub fn applies_to(&self, method: &str) -> bool {
+        method == "GET" || method == "HEAD"
+    }
+
+    // Why the result of an attempt should be retried, if it should be: the
+    // engine answered with a server error, could not be connected to, or
+    // reset or closed the connection, as it does when it restarts. Other
+    // failures, such as timeouts, are returned as they are.
+    pub fn retry_reason<'a>(&self, result: &'a Result<Response>) -> Option<RetryReason<'a>> {
+        match *result {
+            Ok(ref response) if response.status_code >= 500 => {
+                Some(RetryReason::Status(response.status_code))
+            }
+            Err(ref e) => match *e.kind() {
+                ErrorKind::ConnectionFailed(_) | ErrorKind::ConnectTimeout(_) => {
+                    Some(RetryReason::Error(e))
+                }
+                _ if io_cause(e).is_some_and(connection_lost) => Some(RetryReason::Error(e)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // How long to wait after the given attempt failed
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(1 << exponent)
+            .map_or(self.max_backoff, |b| b.min(self.max_backoff));
+
+        if !self.jitter {
+            return backoff;
+        }
+        // A fresh RandomState is randomly seeded, which is all the
+        // randomness needed here
+        let random = RandomState::new().build_hasher().finish();
+        let half = backoff / 2;
+        half + Duration::from_nanos(random % (half.as_nanos() as u64 + 1))
+    }
+
+    pub fn notify(&self, attempt: &RetryAttempt) {
+        for hook in &self.hooks {
+            hook(attempt);
+        }
+    }
+}
+
+// The I/O error a failure came from, if it came from one
+fn io_cause(error: &Error) -> Option<&io::Error> {
+    if let ErrorKind::Io(ref e) = *error.kind() {
+        return Some(e);
+    }
+    let next = error.1.next_error.as_ref()?;
+    match next.downcast_ref::<io::Error>() {
+        Some(e) => Some(e),
+        None => next.downcast_ref::<Error>().and_then(io_cause),
+    }
+}
+
+fn connection_lost(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe
+    )
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("jitter", &self.jitter)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
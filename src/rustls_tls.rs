@@ -0,0 +1,271 @@
+// The TLS backend implemented with rustls, for builds without OpenSSL. It
+// takes the same configuration as the OpenSSL backend in `tls`, except that
+// PKCS#12 identities are not supported.
+
+use std;
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore,
+    SignatureScheme, StreamOwned,
+};
+use rustls_native_certs;
+
+use errors::*;
+use httpstream::{exchange, exchange_streaming, is_timeout, Connector, HttpStream, SetTimeouts,
+                 Timer};
+use proxy;
+use types::{Client, TlsConfig, TlsIdentity, TlsMaterial};
+use utils::http;
+
+pub struct RustlsStream {
+    pub stream: StreamOwned<ClientConnection, TcpStream>,
+    timer: Timer,
+}
+
+impl RustlsStream {
+    pub fn connect(client: &Client) -> Result<RustlsStream> {
+        let timer = Timer::start(&client.timeouts);
+        let tcp_opts = client
+            .tcp_options
+            .as_ref()
+            .chain_err(|| "TLS backend chosen with no TCP information")?;
+
+        let config = match (client.tls_config.as_ref(), client.tls_files.as_ref()) {
+            (Some(config), _) => config.clone(),
+            (None, Some(files)) => TlsConfig::from(files),
+            (None, None) => bail!("TLS backend chosen with no TLS information"),
+        };
+
+        let server_name = config
+            .server_name
+            .clone()
+            .unwrap_or_else(|| tcp_opts.host.clone());
+        let server_name =
+            ServerName::try_from(server_name).chain_err(|| "Invalid server name for TLS")?;
+
+        let connection = ClientConnection::new(Arc::new(build_config(&config)?), server_name)
+            .chain_err(|| "Could not create TLS connection")?;
+
+        // Any proxy tunnel has to be set up before the TLS handshake
+        let tcp_stream = proxy::connect(client, &tcp_opts.host, tcp_opts.port, true, &timer)?;
+        let mut stream = StreamOwned::new(connection, tcp_stream);
+
+        // Complete the handshake now, so that failures are reported when
+        // connecting rather than on the first request. It is part of
+        // connecting, so has the same timeout.
+        stream.set_read_timeout(timer.connect_timeout())?;
+        stream.set_write_timeout(timer.connect_timeout())?;
+        while stream.conn.is_handshaking() {
+            match stream.conn.complete_io(&mut stream.sock) {
+                Ok(_) => (),
+                Err(ref e) if is_timeout(e) => bail!(ErrorKind::ConnectTimeout(
+                    proxy::address(&tcp_opts.host, tcp_opts.port)
+                )),
+                Err(e) => return Err(e).chain_err(|| "TLS handshake error"),
+            }
+        }
+
+        Ok(RustlsStream { stream, timer })
+    }
+}
+
+fn build_config(config: &TlsConfig) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    // Without peer verification there is nothing to check the chain
+    // against, so no roots are loaded
+    let webpki = if config.verify_peer {
+        let roots = roots(config)?;
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .chain_err(|| "Could not create TLS verifier")?;
+        Some(webpki)
+    } else {
+        None
+    };
+    let verifier = Verifier {
+        webpki,
+        provider: provider.clone(),
+        verify_hostname: config.verify_hostname,
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .chain_err(|| "Could not create TLS configuration")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    match config.identity {
+        Some(TlsIdentity::Pem { ref key, ref cert }) => {
+            let certs = certificates(&read(cert)?)?;
+            let key = PrivateKeyDer::from_pem_slice(&read(key)?)
+                .chain_err(|| "Could not parse TLS key")?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .chain_err(|| "Could not set certificate for TLS")
+        }
+        Some(TlsIdentity::Pkcs12 { .. }) => {
+            bail!("PKCS#12 identities are not supported with rustls, use PEM files")
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+// The certificates to trust: only the configured CA when there is one, as
+// with the OpenSSL backend, or else the system's
+fn roots(config: &TlsConfig) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match config.ca {
+        Some(ref ca) => {
+            for cert in certificates(&read(ca)?)? {
+                roots.add(cert).chain_err(|| "Could not set CA for TLS")?;
+            }
+        }
+        None => {
+            // Certificates the system store fails to load are skipped, as
+            // OpenSSL does
+            let native = rustls_native_certs::load_native_certs();
+            roots.add_parsable_certificates(native.certs);
+        }
+    }
+    Ok(roots)
+}
+
+fn read(material: &TlsMaterial) -> Result<Vec<u8>> {
+    match *material {
+        TlsMaterial::File(ref path) => {
+            std::fs::read(path).chain_err(|| format!("Could not read {}", path))
+        }
+        TlsMaterial::Memory(ref pem) => Ok(pem.clone()),
+    }
+}
+
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .chain_err(|| "Could not parse TLS certificate")
+}
+
+// Checks the engine's certificate as far as the configuration asks, with
+// no `webpki` verifier when the engine's certificate is not to be verified
+#[derive(Debug)]
+struct Verifier {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+    verify_hostname: bool,
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        intermediates: &[CertificateDer],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let webpki = match self.webpki {
+            Some(ref webpki) => webpki,
+            None => return Ok(ServerCertVerified::assertion()),
+        };
+
+        let result = webpki.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        match result {
+            Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForName))
+            | Err(rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext {
+                ..
+            })) if !self.verify_hostname => Ok(ServerCertVerified::assertion()),
+            result => result,
+        }
+    }
+
+    // The handshake is still signed with the certificate's key, even when
+    // the certificate itself is not verified
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+impl SetTimeouts for StreamOwned<ClientConnection, TcpStream> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+}
+
+impl HttpStream for RustlsStream {
+    fn request(&mut self, req: http::Request) -> Result<http::Response> {
+        exchange(&mut self.stream, &self.timer, req)
+    }
+
+    fn stream(
+        &mut self,
+        req: http::Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<http::Response> {
+        exchange_streaming(&mut self.stream, &self.timer, req, body, sink)
+    }
+}
+
+// Connects to the engine over TCP using the client's `tcp_options`, with
+// TLS configured from its `tls_config` or `tls_files`. This is the TLS
+// backend's connector when narwhal is built without OpenSSL.
+pub struct RustlsConnector;
+
+impl Connector for RustlsConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        Ok(Box::new(RustlsStream::connect(client)?))
+    }
+}
@@ -0,0 +1,243 @@
+// Reach a remote engine the same way the docker CLI does for `ssh://`
+// hosts, by running `docker system dial-stdio` on the remote machine and
+// speaking HTTP over the ssh process' stdin and stdout.
+
+use std::io;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use errors::*;
+use httpstream::{exchange, exchange_streaming, Connector, HttpStream, SetTimeouts, Timer};
+use types::Client;
+use utils::http::{Request, Response};
+
+// How much of ssh's stderr to keep for error messages. The end is kept,
+// since that is where ssh says why it failed.
+const STDERR_LIMIT: usize = 16 * 1024;
+
+pub struct SshStream {
+    child: Child,
+    pipe: SshPipe,
+    stderr: SshStderr,
+    timer: Timer,
+}
+
+impl SshStream {
+    pub fn connect(client: &Client) -> Result<SshStream> {
+        let timer = Timer::start(&client.timeouts);
+        let ssh_opts = client
+            .ssh_options
+            .as_ref()
+            .chain_err(|| "SSH backend chosen with no SSH information")?;
+
+        let mut command = Command::new(ssh_opts.ssh_command.as_ref().map_or("ssh", |c| c));
+        if let Some(ref user) = ssh_opts.user {
+            command.arg("-l").arg(user);
+        }
+        if let Some(port) = ssh_opts.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        // ssh only takes whole seconds
+        if let Some(timeout) = timer.connect_timeout() {
+            let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+            command.arg("-o").arg(format!("ConnectTimeout={}", secs));
+        }
+        command
+            .arg("--")
+            .arg(&ssh_opts.host)
+            .args(["docker", "system", "dial-stdio"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = command.spawn().chain_err(|| "Could not run ssh")?;
+        // All three were piped above
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+
+        Ok(SshStream {
+            child,
+            pipe: SshPipe::new(stdin, stdout),
+            stderr: SshStderr::new(stderr),
+            timer,
+        })
+    }
+
+    // Anything ssh printed, to explain why the connection failed
+    fn stderr(&mut self) -> String {
+        let _ = self.child.kill();
+        self.stderr.output()
+    }
+}
+
+impl SshStream {
+    fn explain(&mut self, result: Result<Response>) -> Result<Response> {
+        match result {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                if e.is_timeout() {
+                    return Err(e);
+                }
+                let stderr = self.stderr();
+                Err(e).chain_err(|| format!("ssh connection failed: {}", stderr))
+            }
+        }
+    }
+}
+
+impl HttpStream for SshStream {
+    fn request(&mut self, req: Request) -> Result<Response> {
+        let result = exchange(&mut self.pipe, &self.timer, req);
+        self.explain(result)
+    }
+
+    fn stream(
+        &mut self,
+        req: Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<Response> {
+        let result = exchange_streaming(&mut self.pipe, &self.timer, req, body, sink);
+        self.explain(result)
+    }
+}
+
+// The ssh process' stdin and stdout. Pipes cannot be given timeouts, so
+// stdout is read on another thread, which can be waited on with a timeout.
+// Writes are not timed, since ssh reads its input as it arrives.
+struct SshPipe {
+    stdin: ChildStdin,
+    stdout: Receiver<io::Result<Vec<u8>>>,
+    // Read from stdout but not yet returned
+    pending: Vec<u8>,
+    read_timeout: Option<Duration>,
+}
+
+impl SshPipe {
+    fn new<R: Read + Send + 'static>(stdin: ChildStdin, mut stdout: R) -> SshPipe {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                let read = stdout.read(&mut buffer).map(|len| buffer[..len].to_vec());
+                let done = read.as_ref().map_or(true, |data| data.is_empty());
+                if tx.send(read).is_err() || done {
+                    break;
+                }
+            }
+        });
+
+        SshPipe {
+            stdin,
+            stdout: rx,
+            pending: Vec::new(),
+            read_timeout: None,
+        }
+    }
+}
+
+// The ssh process' stderr, which is read as it is written so that ssh never
+// blocks on a full pipe, keeping the last `STDERR_LIMIT` bytes
+struct SshStderr {
+    output: Arc<Mutex<Vec<u8>>>,
+    // Disconnected once everything has been read
+    done: Receiver<()>,
+}
+
+impl SshStderr {
+    fn new<R: Read + Send + 'static>(mut stderr: R) -> SshStderr {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = channel::<()>();
+        let kept = Arc::clone(&output);
+        thread::spawn(move || {
+            let _done = tx;
+            let mut buffer = [0; 4096];
+            while let Ok(len) = stderr.read(&mut buffer) {
+                if len == 0 {
+                    break;
+                }
+                let mut kept = kept.lock().unwrap();
+                kept.extend_from_slice(&buffer[..len]);
+                let excess = kept.len().saturating_sub(STDERR_LIMIT);
+                kept.drain(..excess);
+            }
+        });
+
+        SshStderr { output, done: rx }
+    }
+
+    // What ssh wrote, waiting briefly for the last of it once ssh has been
+    // killed. Anything which inherited stderr, such as a ProxyCommand, can
+    // keep it open, so the wait is bounded.
+    fn output(&self) -> String {
+        let _ = self.done.recv_timeout(Duration::from_secs(1));
+        let output = self.output.lock().unwrap();
+        String::from(String::from_utf8_lossy(&output).trim())
+    }
+}
+
+impl Read for SshPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            let received = match self.read_timeout {
+                Some(timeout) => self.stdout.recv_timeout(timeout),
+                None => self.stdout.recv().map_err(RecvTimeoutError::from),
+            };
+            match received {
+                Ok(data) => self.pending = data?,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "ssh read timed out"))
+                }
+                // The reading thread stops once ssh closes stdout
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for SshPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+impl SetTimeouts for SshPipe {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, _: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for SshStream {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Connects to the engine by running ssh with the client's `ssh_options`
+pub struct SshConnector;
+
+impl Connector for SshConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        Ok(Box::new(SshStream::connect(client)?))
+    }
+}
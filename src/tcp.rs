@@ -6,42 +6,57 @@
 // Code inspired by: https://github.com/ghmlee/rust-docker/blob/master/src/tcp.rs
 
 use std;
-use std::io::Write;
+use std::io::{Read, Write};
 
 use types::Client;
-use httpstream::{read_from_stream, HttpStream};
+use httpstream::{exchange, exchange_streaming, Connector, HttpStream, Timer};
 use errors::*;
+use proxy;
 use utils::http;
 
 pub struct TcpStream {
     stream: std::net::TcpStream,
+    timer: Timer,
 }
 
-impl HttpStream for TcpStream {
-    fn connect(client: Client) -> Result<TcpStream> {
+impl TcpStream {
+    pub fn connect(client: &Client) -> Result<TcpStream> {
+        let timer = Timer::start(&client.timeouts);
         let tcp_opts = client
             .tcp_options
+            .as_ref()
             .chain_err(|| "TCP backend chosen with no TCP information")?;
 
         // First connect with the TCP stream
-        let tcp_stream = std::net::TcpStream::connect((&*tcp_opts.host, tcp_opts.port))
-            .chain_err(|| "Could not initialise TCP stream to engine")?;
+        let tcp_stream = proxy::connect(client, &tcp_opts.host, tcp_opts.port, false, &timer)?;
 
-        Ok(TcpStream { stream: tcp_stream })
+        Ok(TcpStream {
+            stream: tcp_stream,
+            timer,
+        })
     }
+}
 
+impl HttpStream for TcpStream {
     fn request(&mut self, req: http::Request) -> Result<http::Response> {
-        let req_str = http::gen_request_string(req);
+        exchange(&mut self.stream, &self.timer, req)
+    }
 
-        let mut stream = self.stream
-            .try_clone()
-            .chain_err(|| "Could not clone TCP stream")?;
-        let _ = stream.write(req_str.as_bytes());
-        let data = read_from_stream(&mut stream).chain_err(|| "Could not read from tcp stream")?;
+    fn stream(
+        &mut self,
+        req: http::Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<http::Response> {
+        exchange_streaming(&mut self.stream, &self.timer, req, body, sink)
+    }
+}
 
-        let response =
-            http::parse_response(&data).chain_err(|| "Could not parse engine HTTP response")?;
+// Connects to the engine over plain TCP using the client's `tcp_options`
+pub struct TcpConnector;
 
-        Ok(response)
+impl Connector for TcpConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        Ok(Box::new(TcpStream::connect(client)?))
     }
 }
@@ -1,68 +1,213 @@
 use std;
-use std::io::Write;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
 
 use errors::*;
-use types::Client;
+use proxy;
+use types::{Client, TlsConfig, TlsIdentity, TlsMaterial};
 use utils::http;
-use httpstream::{read_from_stream, HttpStream};
+use httpstream::{exchange, exchange_streaming, Connector, HttpStream, SetTimeouts, Timer};
 
 use openssl;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::PKey;
+use openssl::ssl::{HandshakeError, SslConnector, SslConnectorBuilder, SslFiletype, SslMethod,
+                   SslStream, SslVerifyMode};
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+use openssl::x509::X509;
 
 pub struct TlsStream {
     pub tcp_stream: std::net::TcpStream,
     pub stream: openssl::ssl::SslStream<std::net::TcpStream>,
+    timer: Timer,
 }
 
-impl HttpStream for TlsStream {
-    fn connect(client: Client) -> Result<TlsStream> {
+impl TlsStream {
+    pub fn connect(client: &Client) -> Result<TlsStream> {
+        let timer = Timer::start(&client.timeouts);
         let tcp_opts = client
             .tcp_options
+            .as_ref()
             .chain_err(|| "TLS backend chosen with no TCP information")?;
 
-        let tcp_stream = std::net::TcpStream::connect((&*tcp_opts.host, tcp_opts.port))
-            .chain_err(|| "Could not initialise TCP stream to engine")?;
-
-        let tls_opts = client
-            .tls_files
-            .chain_err(|| "TLS backend chosen with no TLS information")?;
-
-        let mut context_builder = openssl::ssl::SslContextBuilder::new(
-            openssl::ssl::SslMethod::tls(),
-        ).chain_err(|| "Could not create SSL context")?;
-
-        context_builder
-            .set_private_key_file(tls_opts.key, openssl::ssl::SslFiletype::PEM)
-            .chain_err(|| "Could not set key file for TLS")?;
+        let config = match (client.tls_config.as_ref(), client.tls_files.as_ref()) {
+            (Some(config), _) => config.clone(),
+            (None, Some(files)) => TlsConfig::from(files),
+            (None, None) => bail!("TLS backend chosen with no TLS information"),
+        };
 
-        context_builder
-            .set_certificate_file(tls_opts.cert, openssl::ssl::SslFiletype::PEM)
-            .chain_err(|| "Could not set certificate for TLS")?;
+        // Any proxy tunnel has to be set up before the TLS handshake
+        let tcp_stream = proxy::connect(client, &tcp_opts.host, tcp_opts.port, true, &timer)?;
 
-        context_builder
-            .set_ca_file(tls_opts.ca)
-            .chain_err(|| "Could not set CA for TLS")?;
-
-        let context = context_builder.build();
+        // The handshake is part of connecting, so has the same timeout
         let stream = tcp_stream
             .try_clone()
             .chain_err(|| "Could not clone TCP stream")?;
-        let ssl = openssl::ssl::Ssl::new(&context).chain_err(|| "Could not create SSL object")?;
-        let ssl_stream = ssl.connect(stream).chain_err(|| "SSL handshake error")?;
+        stream.set_read_timeout(timer.connect_timeout())?;
+        stream.set_write_timeout(timer.connect_timeout())?;
+
+        let connector = build_connector(&config)?;
+        let server_name = config.server_name.as_ref().unwrap_or(&tcp_opts.host);
+
+        let handshake = connector
+            .configure()
+            .chain_err(|| "Could not create SSL object")?
+            .verify_hostname(config.verify_peer && config.verify_hostname)
+            .connect(server_name, stream);
+        let ssl_stream = match handshake {
+            Ok(s) => s,
+            // Only a socket timeout interrupts a blocking handshake
+            Err(HandshakeError::WouldBlock(_)) => bail!(ErrorKind::ConnectTimeout(
+                proxy::address(&tcp_opts.host, tcp_opts.port)
+            )),
+            Err(e) => return Err(e).chain_err(|| "SSL handshake error"),
+        };
 
         Ok(TlsStream {
             tcp_stream,
             stream: ssl_stream,
+            timer,
         })
     }
+}
+
+fn build_connector(config: &TlsConfig) -> Result<SslConnector> {
+    // This starts out trusting the system's certificate store
+    let mut builder =
+        SslConnector::builder(SslMethod::tls()).chain_err(|| "Could not create SSL context")?;
+
+    if let Some(ref identity) = config.identity {
+        set_identity(&mut builder, identity)?;
+    }
+
+    if config.verify_peer {
+        // A configured CA replaces the system's store rather than adding to
+        // it, as with the rustls backend
+        if let Some(ref ca) = config.ca {
+            builder.set_cert_store(ca_store(ca)?);
+        }
+    } else {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
 
+    Ok(builder.build())
+}
+
+// A store trusting only the certificates in `ca`
+fn ca_store(ca: &TlsMaterial) -> Result<X509Store> {
+    let pem = match *ca {
+        TlsMaterial::File(ref path) => {
+            std::fs::read(path).chain_err(|| format!("Could not read {}", path))?
+        }
+        TlsMaterial::Memory(ref pem) => pem.clone(),
+    };
+    let certs = X509::stack_from_pem(&pem).chain_err(|| "Could not parse CA for TLS")?;
+
+    let mut store = X509StoreBuilder::new().chain_err(|| "Could not set CA for TLS")?;
+    for cert in certs {
+        store
+            .add_cert(cert)
+            .chain_err(|| "Could not set CA for TLS")?;
+    }
+    Ok(store.build())
+}
+
+fn set_identity(builder: &mut SslConnectorBuilder, identity: &TlsIdentity) -> Result<()> {
+    match *identity {
+        TlsIdentity::Pem { ref key, ref cert } => {
+            match *key {
+                TlsMaterial::File(ref path) => builder
+                    .set_private_key_file(path, SslFiletype::PEM)
+                    .chain_err(|| "Could not set key file for TLS")?,
+                TlsMaterial::Memory(ref pem) => {
+                    let key =
+                        PKey::private_key_from_pem(pem).chain_err(|| "Could not parse TLS key")?;
+                    builder
+                        .set_private_key(&key)
+                        .chain_err(|| "Could not set key for TLS")?;
+                }
+            }
+            match *cert {
+                TlsMaterial::File(ref path) => builder
+                    .set_certificate_file(path, SslFiletype::PEM)
+                    .chain_err(|| "Could not set certificate for TLS")?,
+                TlsMaterial::Memory(ref pem) => {
+                    let cert =
+                        X509::from_pem(pem).chain_err(|| "Could not parse TLS certificate")?;
+                    builder
+                        .set_certificate(&cert)
+                        .chain_err(|| "Could not set certificate for TLS")?;
+                }
+            }
+        }
+        TlsIdentity::Pkcs12 {
+            ref archive,
+            ref password,
+        } => {
+            let der = match *archive {
+                TlsMaterial::File(ref path) => {
+                    std::fs::read(path).chain_err(|| "Could not read PKCS#12 archive")?
+                }
+                TlsMaterial::Memory(ref der) => der.clone(),
+            };
+            let parsed = Pkcs12::from_der(&der)
+                .and_then(|p| p.parse2(password))
+                .chain_err(|| "Could not parse PKCS#12 archive")?;
+
+            let key = parsed.pkey.chain_err(|| "PKCS#12 archive has no key")?;
+            let cert = parsed.cert.chain_err(|| "PKCS#12 archive has no certificate")?;
+            builder
+                .set_private_key(&key)
+                .chain_err(|| "Could not set key for TLS")?;
+            builder
+                .set_certificate(&cert)
+                .chain_err(|| "Could not set certificate for TLS")?;
+            for ca in parsed.ca.into_iter().flatten() {
+                builder
+                    .add_extra_chain_cert(ca)
+                    .chain_err(|| "Could not set certificate chain for TLS")?;
+            }
+        }
+    }
+
+    builder
+        .check_private_key()
+        .chain_err(|| "TLS key does not match certificate")
+}
+
+impl SetTimeouts for SslStream<TcpStream> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_write_timeout(timeout)
+    }
+}
+
+impl HttpStream for TlsStream {
     fn request(&mut self, req: http::Request) -> Result<http::Response> {
-        let req_str = http::gen_request_string(req);
+        exchange(&mut self.stream, &self.timer, req)
+    }
 
-        let _ = self.stream.write(req_str.as_bytes());
+    fn stream(
+        &mut self,
+        req: http::Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<http::Response> {
+        exchange_streaming(&mut self.stream, &self.timer, req, body, sink)
+    }
+}
 
-        let data =
-            read_from_stream(&mut self.stream).chain_err(|| "Could not read from TLS stream")?;
+// Connects to the engine over TCP using the client's `tcp_options`, with
+// TLS configured from its `tls_config` or `tls_files`
+pub struct TlsConnector;
 
-        http::parse_response(&data)
+impl Connector for TlsConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        Ok(Box::new(TlsStream::connect(client)?))
     }
 }
@@ -1,56 +1,412 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use url::Url;
+
+use build::Builder;
+use cassette::{Cassette, ReplayConnector};
+use containers::Containers;
+use distribution::Distribution;
+use engine::Engine;
+use errors::*;
+use httpstream::{Connector, HttpStream};
+use images::Images;
+use proxy::Proxy;
+use retry::RetryPolicy;
+use ssh::SshConnector;
+use tcp::TcpConnector;
+#[cfg(feature = "openssl")]
+use tls::TlsConnector;
+#[cfg(all(feature = "rustls-tls", not(feature = "openssl")))]
+use rustls_tls::RustlsConnector as TlsConnector;
+use unix::UnixConnector;
+
+#[derive(Clone)]
 pub enum CommsBackend {
     Unix,
     TCP,
     TLS,
+    SSH,
+    Replay,
+    // Connections are made by the client's `connector`
+    Custom,
 }
 
+#[derive(Clone)]
 pub struct TcpClient {
     pub host: String,
     pub port: u16,
 }
 
+#[derive(Clone)]
 pub struct TlsFiles {
     pub key: String,
     pub cert: String,
     pub ca: String,
 }
 
+// A key, certificate or archive, either on disk or in memory
+#[derive(Clone)]
+pub enum TlsMaterial {
+    File(String),
+    // PEM for keys and certificates, DER for a PKCS#12 archive
+    Memory(Vec<u8>),
+}
+
+// The certificate and key a client presents to the engine
+#[derive(Clone)]
+pub enum TlsIdentity {
+    Pem {
+        key: TlsMaterial,
+        cert: TlsMaterial,
+    },
+    // A DER encoded PKCS#12 archive holding the key and certificate
+    Pkcs12 {
+        archive: TlsMaterial,
+        password: String,
+    },
+}
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    // The client certificate, which engines started with `--tlsverify`
+    // require. Leave unset for server-only TLS.
+    pub identity: Option<TlsIdentity>,
+    // The CA to verify the engine's certificate with, or the system trust
+    // store when unset. When set, only this CA is trusted, as with the
+    // docker CLI, whichever TLS backend is used.
+    pub ca: Option<TlsMaterial>,
+    // Verify the engine's certificate chain, as DOCKER_TLS_VERIFY does
+    pub verify_peer: bool,
+    // Also check that the certificate is for the host being connected to
+    pub verify_hostname: bool,
+    // The name sent with SNI and checked against the certificate, when it
+    // differs from the host being connected to
+    pub server_name: Option<String>,
+}
+
+impl TlsConfig {
+    // Server-only TLS, verifying the engine against the system trust store
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            identity: None,
+            ca: None,
+            verify_peer: true,
+            verify_hostname: true,
+            server_name: None,
+        }
+    }
+
+    // Configure TLS the way the docker CLI does, from the key.pem, cert.pem
+    // and ca.pem in DOCKER_CERT_PATH (or ~/.docker), verifying the engine
+    // only when DOCKER_TLS_VERIFY is set
+    pub fn from_env() -> TlsConfig {
+        TlsConfig::from_vars(|name| env::var(name).ok())
+    }
+
+    // As `from_env`, but looking up variables with the given function
+    pub fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> TlsConfig {
+        let cert_path = var("DOCKER_CERT_PATH")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                PathBuf::from(var("HOME").unwrap_or_default()).join(".docker")
+            });
+        let file = |name: &str| {
+            TlsMaterial::File(cert_path.join(name).to_string_lossy().into_owned())
+        };
+        let verify = var("DOCKER_TLS_VERIFY").is_some_and(|v| !v.is_empty());
+
+        TlsConfig {
+            identity: Some(TlsIdentity::Pem {
+                key: file("key.pem"),
+                cert: file("cert.pem"),
+            }),
+            ca: Some(file("ca.pem")),
+            verify_peer: verify,
+            verify_hostname: verify,
+            server_name: None,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig::new()
+    }
+}
+
+// Certificates given as files keep the behaviour of `new_tls`, which does
+// not verify the engine
+impl<'a> From<&'a TlsFiles> for TlsConfig {
+    fn from(files: &'a TlsFiles) -> TlsConfig {
+        TlsConfig {
+            identity: Some(TlsIdentity::Pem {
+                key: TlsMaterial::File(files.key.clone()),
+                cert: TlsMaterial::File(files.cert.clone()),
+            }),
+            ca: Some(TlsMaterial::File(files.ca.clone())),
+            verify_peer: false,
+            verify_hostname: false,
+            server_name: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SshClient {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    // The ssh binary to run, defaulting to `ssh` from the PATH
+    pub ssh_command: Option<String>,
+}
+
+// Limits on how long a request may take. Each is unlimited when unset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timeouts {
+    // Opening the connection, including any proxy tunnel, the TLS handshake
+    // and starting ssh. Unix sockets connect immediately or fail.
+    pub connect: Option<Duration>,
+    // Waiting for the engine to send each part of its response
+    pub read: Option<Duration>,
+    // Waiting for the engine to accept each part of the request
+    pub write: Option<Duration>,
+    // The whole request, from connecting to reading the end of the response
+    pub deadline: Option<Duration>,
+}
+
+impl Timeouts {
+    // Streaming endpoints can go on for as long as the engine keeps sending,
+    // so are only limited by the read timeout, which then applies to the
+    // gap between chunks, and not by the deadline
+    pub fn for_streaming(&self) -> Timeouts {
+        Timeouts {
+            deadline: None,
+            ..self.clone()
+        }
+    }
+}
+
+// How TCP and TLS clients decide whether to connect through a proxy
+#[derive(Clone)]
+pub enum ProxySetting {
+    // Use HTTPS_PROXY, HTTP_PROXY and NO_PROXY, as the docker CLI does
+    Environment,
+    // Always connect to the engine directly
+    Direct,
+    Explicit(Proxy),
+}
+
+// A client is cheap to clone and can be shared between threads. Each
+// request opens its own connection to the engine.
+#[derive(Clone)]
 pub struct Client {
     pub backend: CommsBackend,
     pub socket_path: Option<String>,
     pub tcp_options: Option<TcpClient>,
     pub use_tls: bool,
     pub tls_files: Option<TlsFiles>,
+    // Takes precedence over `tls_files` when set
+    pub tls_config: Option<TlsConfig>,
+    pub ssh_options: Option<SshClient>,
+    pub proxy: ProxySetting,
+    // Records requests when set on another backend, or answers them with
+    // the Replay backend
+    pub cassette: Option<Cassette>,
+    pub connector: Option<Arc<dyn Connector>>,
+    pub timeouts: Timeouts,
+    // Retries GET and HEAD requests when the engine is unavailable
+    pub retry: Option<RetryPolicy>,
 }
 
 impl Client {
     pub fn new_unix(socket_path: String) -> Client {
         Client {
-            backend: CommsBackend::Unix,
             socket_path: Some(socket_path),
-            tcp_options: None,
-            use_tls: false,
-            tls_files: None,
+            ..Client::with_backend(CommsBackend::Unix)
         }
     }
 
     pub fn new_tcp(tcp: TcpClient) -> Client {
         Client {
-            backend: CommsBackend::TCP,
-            socket_path: None,
             tcp_options: Some(tcp),
-            use_tls: false,
-            tls_files: None,
+            ..Client::with_backend(CommsBackend::TCP)
         }
     }
 
     pub fn new_tls(tcp: TcpClient, tls_files: TlsFiles) -> Client {
         Client {
-            backend: CommsBackend::TLS,
-            socket_path: None,
             tcp_options: Some(tcp),
             use_tls: true,
             tls_files: Some(tls_files),
+            ..Client::with_backend(CommsBackend::TLS)
+        }
+    }
+
+    pub fn new_tls_config(tcp: TcpClient, tls_config: TlsConfig) -> Client {
+        Client {
+            tcp_options: Some(tcp),
+            use_tls: true,
+            tls_config: Some(tls_config),
+            ..Client::with_backend(CommsBackend::TLS)
+        }
+    }
+
+    // A client which reaches a remote engine by running
+    // `docker system dial-stdio` over ssh
+    pub fn new_ssh(ssh: SshClient) -> Client {
+        Client {
+            ssh_options: Some(ssh),
+            ..Client::with_backend(CommsBackend::SSH)
         }
     }
+
+    // A client which answers requests from a recorded cassette
+    pub fn new_replay(cassette: Cassette) -> Client {
+        Client {
+            cassette: Some(cassette),
+            ..Client::with_backend(CommsBackend::Replay)
+        }
+    }
+
+    // A client which makes connections with a user supplied connector
+    pub fn new_custom<C: Connector + 'static>(connector: C) -> Client {
+        Client {
+            connector: Some(Arc::new(connector)),
+            ..Client::with_backend(CommsBackend::Custom)
+        }
+    }
+
+    // Create a client from a DOCKER_HOST style address, such as
+    // `unix:///var/run/docker.sock`, `tcp://10.0.0.2:2375` or
+    // `ssh://pi@device.local:2222`. TLS clients need certificates, so use
+    // `new_tls` for those.
+    pub fn from_host(host: &str) -> Result<Client> {
+        let url = Url::parse(host).chain_err(|| format!("Could not parse engine host {}", host))?;
+
+        match url.scheme() {
+            "unix" => Ok(Client::new_unix(String::from(url.path()))),
+            "tcp" => Ok(Client::new_tcp(TcpClient {
+                host: String::from(url.host_str().chain_err(|| "No host in tcp address")?),
+                port: url.port().unwrap_or(2375),
+            })),
+            "ssh" => Ok(Client::new_ssh(SshClient {
+                host: String::from(url.host_str().chain_err(|| "No host in ssh address")?),
+                user: match url.username() {
+                    "" => None,
+                    user => Some(String::from(user)),
+                },
+                port: url.port(),
+                ssh_command: None,
+            })),
+            scheme => bail!("Unsupported engine host scheme: {}", scheme),
+        }
+    }
+
+    fn with_backend(backend: CommsBackend) -> Client {
+        Client {
+            backend,
+            socket_path: None,
+            tcp_options: None,
+            use_tls: false,
+            tls_files: None,
+            tls_config: None,
+            ssh_options: None,
+            proxy: ProxySetting::Environment,
+            cassette: None,
+            connector: None,
+            timeouts: Timeouts::default(),
+            retry: None,
+        }
+    }
+
+    // The connector used to reach the engine, for the chosen backend
+    pub fn connector(&self) -> Arc<dyn Connector> {
+        match self.backend {
+            CommsBackend::Unix => Arc::new(UnixConnector),
+            CommsBackend::TCP => Arc::new(TcpConnector),
+            CommsBackend::TLS => Arc::new(TlsConnector),
+            CommsBackend::SSH => Arc::new(SshConnector),
+            CommsBackend::Replay => Arc::new(ReplayConnector),
+            CommsBackend::Custom => match self.connector {
+                Some(ref c) => c.clone(),
+                None => Arc::new(MissingConnector),
+            },
+        }
+    }
+
+    pub fn engine(&self) -> Engine<'_> {
+        Engine::new(self)
+    }
+
+    pub fn containers(&self) -> Containers<'_> {
+        Containers::new(self)
+    }
+
+    pub fn images(&self) -> Images<'_> {
+        Images::new(self)
+    }
+
+    pub fn distribution(&self) -> Distribution<'_> {
+        Distribution::new(self)
+    }
+
+    pub fn build(&self) -> Builder<'_> {
+        Builder::new(self)
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.backend, CommsBackend::Replay)
+    }
+
+    // Connect through the given proxy, regardless of the environment
+    pub fn with_proxy(mut self, proxy: Proxy) -> Client {
+        self.proxy = ProxySetting::Explicit(proxy);
+        self
+    }
+
+    // Connect directly, ignoring any proxy in the environment
+    pub fn without_proxy(mut self) -> Client {
+        self.proxy = ProxySetting::Direct;
+        self
+    }
+
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Client {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Client {
+        self.retry = Some(policy);
+        self
+    }
+
+    // Record every request made by this client, and the engine's response,
+    // into the given cassette
+    pub fn record_to(mut self, cassette: Cassette) -> Client {
+        self.cassette = Some(cassette);
+        self
+    }
+}
+
+// Stands in for the TLS backend when built without a TLS implementation
+#[cfg(not(any(feature = "openssl", feature = "rustls-tls")))]
+struct TlsConnector;
+
+#[cfg(not(any(feature = "openssl", feature = "rustls-tls")))]
+impl Connector for TlsConnector {
+    fn connect(&self, _: &Client) -> Result<Box<dyn HttpStream>> {
+        bail!("TLS backend chosen without the `openssl` or `rustls-tls` feature")
+    }
+}
+
+struct MissingConnector;
+
+impl Connector for MissingConnector {
+    fn connect(&self, _: &Client) -> Result<Box<dyn HttpStream>> {
+        bail!("Custom backend chosen with no connector")
+    }
 }
@@ -1,41 +1,50 @@
+use std::io::{Read, Write};
 use std::os::unix;
-use std::io::prelude::*;
 
 use errors::*;
 use types::Client;
-use httpstream::{read_from_stream, HttpStream};
-use utils::http;
+use httpstream::{exchange, exchange_streaming, Connector, HttpStream, Timer};
 use utils::http::{Request, Response};
 
 pub struct UnixStream {
     stream: unix::net::UnixStream,
+    timer: Timer,
 }
 
-impl HttpStream for UnixStream {
-    fn connect(client: Client) -> Result<UnixStream> {
+impl UnixStream {
+    pub fn connect(client: &Client) -> Result<UnixStream> {
+        let timer = Timer::start(&client.timeouts);
         let socket_path = client
             .socket_path
+            .as_ref()
             .ok_or("No socket path defined with unix backend")?;
         let stream = unix::net::UnixStream::connect(socket_path)
             .chain_err(|| "Could not connect to unix socket")?;
 
-        Ok(UnixStream { stream })
+        Ok(UnixStream { stream, timer })
     }
+}
 
+impl HttpStream for UnixStream {
     fn request(&mut self, req: Request) -> Result<Response> {
-        let req_str = http::gen_request_string(req);
-
-        let mut stream = self.stream
-            .try_clone()
-            .chain_err(|| "Could not clone unix stream")?;
+        exchange(&mut self.stream, &self.timer, req)
+    }
 
-        stream
-            .write_all(req_str.as_bytes())
-            .chain_err(|| "Could not write to unix stream")?;
+    fn stream(
+        &mut self,
+        req: Request,
+        body: Option<&mut dyn Read>,
+        sink: &mut dyn Write,
+    ) -> Result<Response> {
+        exchange_streaming(&mut self.stream, &self.timer, req, body, sink)
+    }
+}
 
-        let response_str =
-            read_from_stream(&mut stream).chain_err(|| "Could not read from unix stream")?;
+// Connects to the engine over the client's `socket_path`
+pub struct UnixConnector;
 
-        http::parse_response(&response_str).chain_err(|| "Could not parse HTTP response")
+impl Connector for UnixConnector {
+    fn connect(&self, client: &Client) -> Result<Box<dyn HttpStream>> {
+        Ok(Box::new(UnixStream::connect(client)?))
     }
 }
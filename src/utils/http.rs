@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use regex::Regex;
 use errors::*;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Request {
     pub method: String,
     pub path: String,
@@ -10,6 +11,7 @@ pub struct Request {
     pub body: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Response {
     pub status_code: u16,
     pub headers: HashMap<String, String>,
@@ -41,6 +43,28 @@ pub fn gen_request_string(request: Request) -> String {
 }
 
 pub fn parse_response(response: &str) -> Result<Response> {
+    // The headers end at the first empty line, and the rest is the body
+    let (head, body) = match response.find("\r\n\r\n") {
+        Some(idx) => (&response[..idx], &response[idx + 4..]),
+        None => (response, ""),
+    };
+
+    let mut res = parse_response_head(head)?;
+    res.body = String::from(body);
+
+    if let Some(value) = res.headers.get("Transfer-Encoding") {
+        if value == "chunked" {
+            let parsed =
+                parse_chunked(&res.body).chain_err(|| "Could not parse chunked HTTP body")?;
+            res.body = parsed;
+        }
+    }
+
+    Ok(res)
+}
+
+// Parse the status line and headers of a response, leaving the body empty
+pub fn parse_response_head(head: &str) -> Result<Response> {
     // setup some regexes
     lazy_static! {
         static ref STATUS_LINE_RE: Regex = Regex::new(r"HTTP/\d+\.\d+ (\d+) \w+").unwrap();
@@ -48,13 +72,14 @@ pub fn parse_response(response: &str) -> Result<Response> {
     }
 
     // First split the input into lines based on delimeters
-    let mut parts = response.split("\r\n");
+    let mut parts = head.split("\r\n");
 
     // The first line in the response should be the status line
     let status_line = parts.next().ok_or("Could not parse HTTP response")?;
     let captures = STATUS_LINE_RE
         .captures(status_line)
         .chain_err(|| "Could not parse status line of HTTP response")?;
+
     let status_code = captures
         .get(1)
         .chain_err(|| "Could not parse status code of HTTP response")?
@@ -68,39 +93,15 @@ pub fn parse_response(response: &str) -> Result<Response> {
         body: String::new(),
     };
 
-    // Loop through the rest of the parts until we reach an empty line
-    let mut in_body = false;
-    let mut added_to_body = false;
-    for l in parts {
-        if in_body {
-            // The remaining parts are all body
-            if added_to_body {
-                res.body.push_str("\r\n");
-            }
-            res.body.push_str(l);
-            added_to_body = true;
-        } else {
-            if l == "" {
-                in_body = true;
-                continue;
-            }
-            // This must be a HTTP header, parse it as such
-            let captures = HEADER_LINE_RE
-                .captures(l)
-                .chain_err(|| "Could not parse HTTP headers")?;
-
-            let name = String::from(captures.get(1).unwrap().as_str());
-            let value = String::from(captures.get(2).unwrap().as_str());
-            res.headers.insert(name, value);
-        }
-    }
+    // The rest of the lines are HTTP headers, up to any empty line
+    for l in parts.take_while(|l| !l.is_empty()) {
+        let captures = HEADER_LINE_RE
+            .captures(l)
+            .chain_err(|| "Could not parse HTTP headers")?;
 
-    if let Some(value) = res.headers.get("Transfer-Encoding") {
-        if value == "chunked" {
-            let parsed =
-                parse_chunked(&res.body).chain_err(|| "Could not parse chunked HTTP body")?;
-            res.body = parsed;
-        }
+        let name = String::from(captures.get(1).unwrap().as_str());
+        let value = String::from(captures.get(2).unwrap().as_str());
+        res.headers.insert(name, value);
     }
 
     Ok(res)
@@ -140,3 +141,49 @@ pub fn parse_chunked(body: &str) -> Result<String> {
 
     Ok(parsed)
 }
+
+// Decode a chunked HTTP body from raw bytes, returning the decoded body and
+// the number of bytes the encoding took up, or None if the terminating
+// chunk has not been received yet
+pub fn decode_chunked(data: &[u8]) -> Result<Option<(Vec<u8>, usize)>> {
+    let mut decoded = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let line_end = match find_crlf(&data[pos..]) {
+            Some(idx) => pos + idx,
+            None => return Ok(None),
+        };
+        let size_line = String::from_utf8_lossy(&data[pos..line_end]);
+        // Ignore any chunk extensions
+        let size_str = size_line.split(';').next().unwrap_or("").trim();
+        let size =
+            usize::from_str_radix(size_str, 16).chain_err(|| "Could not parse chunk length")?;
+        pos = line_end + 2;
+
+        if size == 0 {
+            // Skip any trailer headers up to the final empty line
+            loop {
+                let line_end = match find_crlf(&data[pos..]) {
+                    Some(idx) => pos + idx,
+                    None => return Ok(None),
+                };
+                let empty = line_end == pos;
+                pos = line_end + 2;
+                if empty {
+                    return Ok(Some((decoded, pos)));
+                }
+            }
+        }
+
+        if data.len() < pos + size + 2 {
+            return Ok(None);
+        }
+        decoded.extend_from_slice(&data[pos..pos + size]);
+        pos += size + 2;
+    }
+}
+
+fn find_crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}